@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+use crate::chat::ChatMessage;
+use crate::moderation::{Command, DEFAULT_TIMEOUT};
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+// Twitch allows moderators 100 commands every 30 seconds, stay comfortably under it so a raid
+// cleanup never gets us disconnected for flooding.
+const RATE_WINDOW: Duration = Duration::from_secs(30);
+const RATE_LIMIT: usize = 80;
+const MIN_SPACING: Duration = Duration::from_millis(300);
+
+const HEADER_BG: [f32; 3] = [0.3, 0.15, 0.1];
+const HEADER_FG: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BulkAction {
+    Timeout,
+    Ban,
+}
+
+struct Target {
+    user: String,
+    // Where they said it, which is where the action has to happen
+    channel: String,
+    messages: usize,
+    sample: String,
+    include: bool,
+}
+
+/// Lists the distinct authors of the selected messages so the moderator can check who is about
+/// to be actioned before anything is sent.
+pub struct BulkPreview {
+    action: BulkAction,
+    targets: Vec<Target>,
+    cursor: usize,
+}
+
+impl BulkPreview {
+    pub fn new(action: BulkAction, messages: &[&ChatMessage]) -> Self {
+        let mut targets: Vec<Target> = Vec::new();
+        for m in messages {
+            if m.sender.is_empty() {
+                continue;
+            }
+            match targets.iter_mut().find(|t| t.user == m.sender && t.channel == m.channel) {
+                Some(t) => t.messages += 1,
+                None => {
+                    // Twitch refuses to action these, so leave them out unless asked
+                    let protected = m.badges().iter()
+                        .any(|(b, _)| matches!(*b, "broadcaster" | "moderator" | "staff" | "admin"));
+                    targets.push(Target {
+                        user: m.sender.clone(),
+                        channel: m.channel.clone(),
+                        messages: 1,
+                        sample: m.message.clone(),
+                        include: !protected,
+                    });
+                },
+            }
+        }
+        Self { action, targets, cursor: 0 }
+    }
+
+    pub fn move_cursor(&mut self, delta: i32) {
+        let last = self.targets.len().saturating_sub(1) as i64;
+        self.cursor = (self.cursor as i64 + delta as i64).clamp(0, last) as usize;
+    }
+
+    pub fn toggle(&mut self) {
+        if let Some(t) = self.targets.get_mut(self.cursor) {
+            t.include = !t.include;
+        }
+    }
+
+    /// The commands to run for every author still included, with the channel to run them in.
+    pub fn commands(&self) -> Vec<(String, Command)> {
+        self.targets.iter()
+            .filter(|t| t.include)
+            .map(|t| (t.channel.clone(), match self.action {
+                BulkAction::Timeout => Command::Timeout {
+                    user: t.user.clone(),
+                    seconds: DEFAULT_TIMEOUT,
+                    reason: String::new(),
+                },
+                BulkAction::Ban => Command::Ban { user: t.user.clone(), reason: String::new() },
+            }))
+            .collect()
+    }
+
+    pub fn draw(&self, screen: &mut Screen, theme: &Theme) {
+        let included = self.targets.iter().filter(|t| t.include).count();
+        let verb = match self.action {
+            BulkAction::Timeout => "Timeout",
+            BulkAction::Ban => "Ban",
+        };
+        screen.fill_row(0, HEADER_BG);
+        screen.print_colored(
+            0, 1,
+            &format!("{} {} of {} users?  Enter to confirm, Space to toggle, Esc to cancel", verb, included, self.targets.len()),
+            HEADER_FG, HEADER_BG,
+        );
+
+        let rows = screen.rows() as usize;
+        let width = screen.cols().saturating_sub(2) as usize;
+        // Keep the cursor on screen
+        let first = self.cursor.saturating_sub(rows.saturating_sub(2));
+        for (row, (i, t)) in self.targets.iter().enumerate().skip(first).enumerate() {
+            let row = row as u32 + 1;
+            if row as usize >= rows {
+                break;
+            }
+            let line = format!(
+                "[{}] {:<25} #{:<12} {:>3} msgs  {}",
+                if t.include { "x" } else { " " },
+                t.user,
+                t.channel,
+                t.messages,
+                t.sample,
+            );
+            let line: String = line.chars().take(width).collect();
+            let fg = if t.include { theme.text() } else { theme.meta() };
+            let bg = if i == self.cursor { theme.cursor() } else { theme.background() };
+            screen.print_colored(row, 1, &line, fg, bg);
+        }
+    }
+}
+
+/// Sends moderation commands no faster than Twitch's rate limit allows.
+pub struct ModQueue {
+    pending: VecDeque<String>,
+    sent: VecDeque<Instant>,
+}
+
+impl ModQueue {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, irc: String) {
+        self.pending.push_back(irc);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// When the next queued command may go out, None if there is nothing queued.
+    pub fn next_ready(&self, now: Instant) -> Option<Instant> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let mut ready = now;
+        if let Some(last) = self.sent.back() {
+            ready = ready.max(*last + MIN_SPACING);
+        }
+        if self.sent.len() >= RATE_LIMIT {
+            ready = ready.max(self.sent[self.sent.len() - RATE_LIMIT] + RATE_WINDOW);
+        }
+        Some(ready)
+    }
+
+    /// Sends whatever the rate limit allows right now, returning how many went out.
+    pub fn poll(&mut self, now: Instant, outbound: &UnboundedSender<String>) -> usize {
+        while self.sent.front().is_some_and(|t| now.duration_since(*t) > RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+        let mut count = 0;
+        while self.next_ready(now).is_some_and(|t| t <= now) {
+            let irc = self.pending.pop_front().unwrap();
+            if outbound.send(irc).is_err() {
+                self.pending.clear();
+                break;
+            }
+            self.sent.push_back(now);
+            count += 1;
+        }
+        count
+    }
+}
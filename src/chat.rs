@@ -1,63 +1,404 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 use futures_util::{SinkExt, StreamExt};
 use tokio_tungstenite::{
     connect_async,
     tungstenite::{Result, Message},
 };
 use ringbuf::Producer;
+use tokio::sync::mpsc::UnboundedReceiver;
 use url::Url;
-use regex::Regex;
+use crate::emotes::{self, EmoteRef};
 
+/// A single line of IRC as sent by Twitch, split into its tags, prefix, command and params.
+pub struct IrcMessage {
+    pub tags: HashMap<String, String>,
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn parse(line: &str) -> Option<IrcMessage> {
+        let mut rest = line.trim_end_matches(&['\r', '\n'][..]);
+
+        let mut tags = HashMap::new();
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let (raw_tags, remaining) = stripped.split_once(' ')?;
+            tags = parse_tags(raw_tags);
+            rest = remaining.trim_start();
+        }
+
+        let mut prefix = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (p, remaining) = stripped.split_once(' ')?;
+            prefix = Some(p.to_string());
+            rest = remaining.trim_start();
+        }
+
+        let (command, mut rest) = match rest.split_once(' ') {
+            Some((c, r)) => (c.to_string(), r),
+            None => (rest.to_string(), ""),
+        };
+        if command.is_empty() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        while !rest.is_empty() {
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            match rest.split_once(' ') {
+                Some((p, r)) => {
+                    params.push(p.to_string());
+                    rest = r.trim_start();
+                },
+                None => {
+                    params.push(rest.to_string());
+                    break;
+                },
+            }
+        }
+
+        Some(Self { tags, prefix, command, params })
+    }
+
+    /// The nick portion of a `nick!user@host` prefix, None for messages from the server itself.
+    pub fn nick(&self) -> Option<&str> {
+        self.prefix.as_deref()?.split_once('!').map(|(nick, _)| nick)
+    }
+}
+
+/// Parses tags in their IRC wire form, `key=value;key=value`, without the leading `@`.
+pub fn parse_tags(raw: &str) -> HashMap<String, String> {
+    raw.split(';')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| {
+            let (k, v) = tag.split_once('=').unwrap_or((tag, ""));
+            (k.to_string(), unescape_tag(v))
+        })
+        .collect()
+}
+
+fn escape_tag(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            ';' => out.push_str("\\:"),
+            ' ' => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape_tag(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    let mut chars = v.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {},
+        }
+    }
+    out
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    Chat,
+    Action,
+    Notice,
+    UserNotice,
+}
+
+impl MessageKind {
+    pub fn from_name(name: &str) -> Option<MessageKind> {
+        match name {
+            "chat" | "privmsg" => Some(MessageKind::Chat),
+            "action" | "me" => Some(MessageKind::Action),
+            "notice" => Some(MessageKind::Notice),
+            "usernotice" => Some(MessageKind::UserNotice),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageKind::Chat => "chat",
+            MessageKind::Action => "action",
+            MessageKind::Notice => "notice",
+            MessageKind::UserNotice => "usernotice",
+        }
+    }
+}
+
+// The colours Twitch hands out to users who never picked one
+const DEFAULT_COLORS: &[[u8; 3]] = &[
+    [0xFF, 0x00, 0x00], [0x00, 0x00, 0xFF], [0x00, 0x80, 0x00], [0xB2, 0x22, 0x22],
+    [0xFF, 0x7F, 0x50], [0x9A, 0xCD, 0x32], [0xFF, 0x45, 0x00], [0x2E, 0x8B, 0x57],
+    [0xDA, 0xA5, 0x20], [0xD2, 0x69, 0x1E], [0x5F, 0x9E, 0xA0], [0x1E, 0x90, 0xFF],
+    [0xFF, 0x69, 0xB4], [0x8A, 0x2B, 0xE2], [0x00, 0xFF, 0x7F],
+];
+
+#[derive(Clone)]
 pub struct ChatMessage {
+    pub id: String,
+    pub channel: String,
     pub sender: String,
     pub message: String,
+    pub kind: MessageKind,
+    /// Milliseconds since the unix epoch, from `tmi-sent-ts` when Twitch provides it.
+    pub timestamp: i64,
+    pub tags: HashMap<String, String>,
 }
 
 impl ChatMessage {
     fn parse(s: String) -> Option<ChatMessage> {
-        let re = Regex::new(r":([^:]+)![^:]+:(.+)").unwrap();
-        let cap = match re.captures(&s) {
-            None => { return None; }
-            Some(c) => c,
+        let irc = IrcMessage::parse(&s)?;
+        let channel = irc.params.first()?.trim_start_matches('#').to_string();
+        let text = irc.params.get(1).cloned().unwrap_or_default();
+
+        let (kind, message) = match irc.command.as_str() {
+            "PRIVMSG" => match text.strip_prefix("\u{1}ACTION ") {
+                Some(action) => (MessageKind::Action, action.trim_end_matches('\u{1}').to_string()),
+                None => (MessageKind::Chat, text),
+            },
+            "NOTICE" => (MessageKind::Notice, text),
+            // Subs, raids and friends carry their human readable text in system-msg, with the
+            // optional user supplied text as the trailing param.
+            "USERNOTICE" => {
+                let system = irc.tags.get("system-msg").cloned().unwrap_or_default();
+                if text.is_empty() {
+                    (MessageKind::UserNotice, system)
+                } else {
+                    (MessageKind::UserNotice, format!("{} {}", system, text))
+                }
+            },
+            _ => return None,
         };
 
+        let sender = irc.tags.get("login").cloned()
+            .or_else(|| irc.nick().map(str::to_string))
+            .unwrap_or_default();
+
+        let timestamp = irc.tags.get("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .unwrap_or_else(now_millis);
+
         Some(Self {
-            sender: cap[1].to_string(),
-            message: cap[2].to_string(),
+            id: irc.tags.get("id").cloned().unwrap_or_default(),
+            channel,
+            sender,
+            message,
+            kind,
+            timestamp,
+            tags: irc.tags,
         })
     }
 
-    fn string(&self) -> String {
-        format!("{}: {}", self.sender, self.message)
+    /// A message we sent, built from what we typed and the tags of the USERSTATE confirming it.
+    pub fn sent(channel: &str, sender: &str, text: &str, tags: HashMap<String, String>) -> Self {
+        let (kind, message) = match text.strip_prefix("/me ") {
+            Some(action) => (MessageKind::Action, action),
+            None => (MessageKind::Chat, text),
+        };
+        Self {
+            id: tags.get("id").cloned().unwrap_or_default(),
+            channel: channel.to_string(),
+            sender: sender.to_lowercase(),
+            message: message.to_string(),
+            kind,
+            timestamp: now_millis(),
+            tags,
+        }
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str).filter(|v| !v.is_empty())
+    }
+
+    /// Badges as (name, version) pairs, e.g. `("moderator", "1")`.
+    pub fn badges(&self) -> Vec<(&str, &str)> {
+        match self.tag("badges") {
+            None => Vec::new(),
+            Some(b) => b.split(',')
+                .filter_map(|badge| badge.split_once('/'))
+                .collect(),
+        }
     }
+
+    /// The name as the user styles it, falling back to their login.
+    pub fn display_name(&self) -> &str {
+        self.tag("display-name").unwrap_or(&self.sender)
+    }
+
+    /// Whether the message is addressed to `nick`, by name or as a reply to one of theirs.
+    pub fn mentions(&self, nick: &str) -> bool {
+        if nick.is_empty() {
+            return false;
+        }
+        self.tag("reply-parent-user-login").is_some_and(|login| login.eq_ignore_ascii_case(nick))
+            || self.message.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .any(|word| word.eq_ignore_ascii_case(nick))
+    }
+
+    /// The user's chosen name colour, or a stable pick from Twitch's default palette for users
+    /// that never set one.
+    pub fn color(&self) -> [u8; 3] {
+        if let Some(hex) = self.tag("color").and_then(|c| c.strip_prefix('#')) {
+            if let Ok(rgb) = u32::from_str_radix(hex, 16) {
+                return [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+            }
+        }
+        default_color(&self.sender)
+    }
+
+    pub fn emotes(&self) -> Vec<EmoteRef> {
+        self.tag("emotes").map(emotes::parse).unwrap_or_default()
+    }
+
+    /// The tags in their IRC wire form, `key=value;key=value`.
+    pub fn tags_string(&self) -> String {
+        let mut tags: Vec<_> = self.tags.iter().collect();
+        tags.sort();
+        tags.iter()
+            .map(|(k, v)| format!("{}={}", k, escape_tag(v)))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+/// A change to who is in a channel, from the membership capability.
+#[derive(Clone, Debug)]
+pub enum Membership {
+    /// One 353 reply, Twitch splits the full list over as many as it needs.
+    Names(Vec<String>),
+    /// The 366 reply closing the list.
+    EndOfNames,
+    Join(String),
+    Part(String),
+    /// `MODE +o`/`-o`, true when the user gained moderator.
+    Mode(String, bool),
+}
+
+/// Everything the connection hands to the UI.
+#[derive(Clone)]
+pub enum ChatEvent {
+    Message(ChatMessage),
+    Membership { channel: String, change: Membership },
+    /// Our own state in a channel. Twitch sends one after each message we send, with that
+    /// message's id, since it doesn't echo the message itself.
+    UserState { channel: String, tags: HashMap<String, String> },
+}
+
+impl ChatEvent {
+    fn parse(s: String) -> Option<ChatEvent> {
+        let irc = IrcMessage::parse(&s)?;
+        let channel = |i: usize| irc.params.get(i).map(|c| c.trim_start_matches('#').to_string());
+        let change = match irc.command.as_str() {
+            // :nick.tmi.twitch.tv 353 nick = #channel :user1 user2 ...
+            "353" => Membership::Names(
+                irc.params.get(3)?.split_whitespace().map(str::to_lowercase).collect(),
+            ),
+            "366" => {
+                return Some(ChatEvent::Membership { channel: channel(1)?, change: Membership::EndOfNames });
+            },
+            "JOIN" => Membership::Join(irc.nick()?.to_lowercase()),
+            "PART" => Membership::Part(irc.nick()?.to_lowercase()),
+            "MODE" => {
+                let user = irc.params.get(2)?.to_lowercase();
+                match irc.params.get(1)?.as_str() {
+                    "+o" => Membership::Mode(user, true),
+                    "-o" => Membership::Mode(user, false),
+                    _ => return None,
+                }
+            },
+            "USERSTATE" => return Some(ChatEvent::UserState { channel: channel(0)?, tags: irc.tags }),
+            _ => return ChatMessage::parse(s).map(ChatEvent::Message),
+        };
+        let channel = match change {
+            Membership::Names(_) => channel(2)?,
+            _ => channel(0)?,
+        };
+        Some(ChatEvent::Membership { channel, change })
+    }
+}
+
+/// A stable pick from Twitch's default palette for a name.
+pub fn default_color(name: &str) -> [u8; 3] {
+    let hash = name.bytes().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    DEFAULT_COLORS[hash % DEFAULT_COLORS.len()]
 }
 
+pub fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+}
 
-pub async fn read_chat(token: String, nick: String, mut prod: Producer<String>) -> Result<()> {
+/// Connects to Twitch chat, pushing parsed events to `prod` and sending every raw IRC line that
+/// arrives on `outbound`.
+pub async fn read_chat(
+    token: String,
+    nick: String,
+    channels: Vec<String>,
+    mut prod: Producer<ChatEvent>,
+    mut outbound: UnboundedReceiver<String>,
+) -> Result<()> {
     println!("Connecting to chat...");
     let (mut socket, _) = connect_async( Url::parse("wss://irc-ws.chat.twitch.tv:443").expect("Can't parse url")).await?;
 
     println!("Connected to chat");
+    socket.send(Message::Text("CAP REQ :twitch.tv/tags twitch.tv/commands twitch.tv/membership".to_string())).await?;
     socket.send(Message::Text(format!("PASS {}", token))).await?;
     socket.send(Message::Text(format!("NICK {}", nick))).await?;
-    socket.send(Message::Text("JOIN #bnans".to_string())).await?;
+    let join: Vec<String> = channels.iter().map(|c| format!("#{}", c)).collect();
+    socket.send(Message::Text(format!("JOIN {}", join.join(",")))).await?;
 
-    while let Some(msg) = socket.next().await {
-        let msg = msg?;
-        if msg.is_text() {
-            for payload in msg.into_text().unwrap().split("\r\n") {
-                if payload.len() == 0 { continue } 
+    let mut outbound_open = true;
+    loop {
+        tokio::select! {
+            msg = socket.next() => {
+                let msg = match msg {
+                    Some(msg) => msg?,
+                    None => break,
+                };
+                if !msg.is_text() {
+                    continue;
+                }
+                for payload in msg.into_text().unwrap().split("\r\n") {
+                    if payload.is_empty() { continue }
 
-                // TODO: match PING with PONG
+                    if let Some(server) = payload.strip_prefix("PING ") {
+                        socket.send(Message::Text(format!("PONG {}", server))).await?;
+                        continue;
+                    }
 
-                let m = match ChatMessage::parse(payload.to_string()) {
-                    Some(m) => m,
-                    None => { continue },
-                };
-                match prod.push(m.string()) {
-                    Ok(_) => {},
-                    Err(e) => { println!("Error writing to buffer: {}", e); }
+                    let event = match ChatEvent::parse(payload.to_string()) {
+                        Some(e) => e,
+                        None => { continue },
+                    };
+                    if prod.push(event).is_err() {
+                        println!("Error writing to buffer: buffer full");
+                    }
                 }
-            }
+            },
+            line = outbound.recv(), if outbound_open => match line {
+                Some(line) => socket.send(Message::Text(line)).await?,
+                None => outbound_open = false,
+            },
         }
     }
     Ok(())
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone, Utc};
+use serde_json::json;
+use crate::chat::{ChatMessage, MessageKind};
+
+// Messages waiting for the disk before we start dropping them rather than blocking the render
+// loop.
+const QUEUE_LEN: usize = 4096;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// irssi style `HH:MM <nick> message`
+    Text,
+    /// One JSON object per line with every tag
+    Jsonl,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" | "irssi" => Some(Format::Text),
+            "jsonl" | "json" => Some(Format::Jsonl),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Text => "log",
+            Format::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// Which midnight the logs rotate at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Local,
+    Utc,
+}
+
+pub struct LogConfig {
+    formats: Vec<Format>,
+    rotation: Rotation,
+    dir: PathBuf,
+}
+
+impl LogConfig {
+    /// Reads `LOG_FORMATS` (comma separated `text`/`jsonl`) and `LOG_TIMEZONE` (`local` or
+    /// `utc`). Logging is off unless at least one format is given.
+    pub fn from_env() -> Option<LogConfig> {
+        let formats: Vec<Format> = env::var("LOG_FORMATS").ok()?
+            .split(',')
+            .filter_map(|f| {
+                let format = Format::from_name(f.trim());
+                if format.is_none() {
+                    println!("Unknown log format {:?}, expected text or jsonl", f);
+                }
+                format
+            })
+            .collect();
+        if formats.is_empty() {
+            return None;
+        }
+
+        let rotation = match env::var("LOG_TIMEZONE").as_deref() {
+            Ok("utc") | Ok("UTC") => Rotation::Utc,
+            _ => Rotation::Local,
+        };
+
+        Some(Self {
+            formats,
+            rotation,
+            dir: crate::config::data_dir().join("logs"),
+        })
+    }
+}
+
+/// Writes daily per-channel log files from a background thread.
+pub struct ChatLogger {
+    tx: SyncSender<ChatMessage>,
+    dropped: u64,
+}
+
+impl ChatLogger {
+    pub fn spawn(config: LogConfig) -> Self {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        thread::Builder::new()
+            .name("chatlog".into())
+            .spawn(move || write_loop(config, rx))
+            .expect("failed to spawn chat log thread");
+        Self { tx, dropped: 0 }
+    }
+
+    /// Queues a message for logging, never waiting on the disk.
+    pub fn log(&mut self, m: &ChatMessage) {
+        match self.tx.try_send(m.clone()) {
+            Ok(_) => {},
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                if self.dropped.is_power_of_two() {
+                    println!("Chat log is falling behind, {} messages dropped", self.dropped);
+                }
+            },
+            Err(TrySendError::Disconnected(_)) => {},
+        }
+    }
+}
+
+struct OpenLog {
+    date: NaiveDate,
+    out: BufWriter<File>,
+}
+
+fn write_loop(config: LogConfig, rx: Receiver<ChatMessage>) {
+    let mut files: HashMap<(String, Format), OpenLog> = HashMap::new();
+
+    while let Ok(first) = rx.recv() {
+        let mut batch = vec![first];
+        batch.extend(rx.try_iter());
+
+        for m in &batch {
+            let time = match timestamp_in(m.timestamp, config.rotation) {
+                Some(t) => t,
+                None => continue,
+            };
+            for format in &config.formats {
+                let key = (m.channel.clone(), *format);
+                let stale = files.get(&key).is_none_or(|f| f.date != time.date_naive());
+                if stale {
+                    match open_log(&config, &m.channel, *format, &time) {
+                        Some(log) => { files.insert(key.clone(), log); },
+                        None => continue,
+                    }
+                }
+                let log = files.get_mut(&key).unwrap();
+                let line = match format {
+                    Format::Text => text_line(m, &time),
+                    Format::Jsonl => json_line(m, &time),
+                };
+                if let Err(e) = writeln!(log.out, "{}", line) {
+                    println!("Failed writing chat log for #{}: {}", m.channel, e);
+                }
+            }
+        }
+
+        for log in files.values_mut() {
+            let _ = log.out.flush();
+        }
+    }
+}
+
+fn timestamp_in(timestamp: i64, rotation: Rotation) -> Option<DateTime<FixedOffset>> {
+    match rotation {
+        Rotation::Local => Local.timestamp_millis_opt(timestamp).single().map(|t| t.fixed_offset()),
+        Rotation::Utc => Utc.timestamp_millis_opt(timestamp).single().map(|t| t.fixed_offset()),
+    }
+}
+
+fn open_log(config: &LogConfig, channel: &str, format: Format, time: &DateTime<FixedOffset>) -> Option<OpenLog> {
+    let dir = config.dir.join(channel);
+    let path = dir.join(format!("{}.{}", time.format("%Y-%m-%d"), format.extension()));
+    let file = fs::create_dir_all(&dir)
+        .and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+    let mut out = match file {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => {
+            println!("Failed to open chat log {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    if format == Format::Text {
+        let _ = writeln!(out, "--- Log opened {}", time.format("%a %b %d %H:%M:%S %Y"));
+    }
+    Some(OpenLog {
+        date: time.date_naive(),
+        out,
+    })
+}
+
+fn text_line(m: &ChatMessage, time: &DateTime<FixedOffset>) -> String {
+    let stamp = time.format("%H:%M");
+    match m.kind {
+        MessageKind::Chat => format!("{} <{}> {}", stamp, m.sender, m.message),
+        MessageKind::Action => format!("{}  * {} {}", stamp, m.sender, m.message),
+        MessageKind::Notice | MessageKind::UserNotice => format!("{} -!- {}", stamp, m.message),
+    }
+}
+
+fn json_line(m: &ChatMessage, time: &DateTime<FixedOffset>) -> String {
+    json!({
+        "time": time.to_rfc3339(),
+        "ts": m.timestamp,
+        "channel": m.channel,
+        "id": m.id,
+        "sender": m.sender,
+        "kind": m.kind.name(),
+        "text": m.message,
+        "tags": m.tags,
+    }).to_string()
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use crate::chat::{ChatMessage, Membership};
+use crate::input::InputLine;
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+// Above 1000 chatters Twitch only lists moderators in NAMES and stops sending JOIN/PART, which
+// can't be told from the size of the list. Instead, JOINs are batched every ten seconds or so, so
+// if none has come for this long while more than UNANNOUNCED_LIMIT people talked without one,
+// membership has gone quiet.
+const QUIET_AFTER: Duration = Duration::from_secs(30);
+const UNANNOUNCED_LIMIT: usize = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Moderator,
+    Vip,
+    Viewer,
+}
+
+impl Role {
+    fn heading(&self) -> &'static str {
+        match self {
+            Role::Moderator => "Moderators",
+            Role::Vip => "VIPs",
+            Role::Viewer => "Viewers",
+        }
+    }
+}
+
+/// Who is currently in the channel, kept up to date from the membership events.
+pub struct Chatters {
+    present: BTreeSet<String>,
+    // Roles outlive a PART so someone rejoining keeps theirs
+    roles: HashMap<String, Role>,
+    // Names from 353 replies waiting for the 366 that ends the list
+    pending: Vec<String>,
+    // The finished NAMES list, all of them moderators if the channel turns out to be partial
+    names: HashSet<String>,
+    // When the 366, or the latest JOIN or PART, arrived
+    last_membership: Option<Instant>,
+    // People who talked within QUIET_AFTER without a JOIN telling us they were here
+    unannounced: VecDeque<(String, Instant)>,
+    // The channel is past the cutoff, the list only holds moderators and who we have seen
+    partial: bool,
+}
+
+impl Chatters {
+    pub fn new() -> Self {
+        Self {
+            present: BTreeSet::new(),
+            roles: HashMap::new(),
+            pending: Vec::new(),
+            names: HashSet::new(),
+            last_membership: None,
+            unannounced: VecDeque::new(),
+            partial: false,
+        }
+    }
+
+    pub fn apply(&mut self, change: Membership, now: Instant) {
+        match change {
+            Membership::Names(names) => self.pending.extend(names),
+            // The NAMES list is a snapshot taken when we joined, JOINs that arrive while it is
+            // still coming in are already in `present` so merge rather than replace.
+            Membership::EndOfNames => {
+                self.names = self.pending.iter().cloned().collect();
+                self.present.extend(self.pending.drain(..));
+                self.last_membership = Some(now);
+            },
+            Membership::Join(user) => {
+                self.unannounced.retain(|(u, _)| *u != user);
+                self.present.insert(user);
+                self.heard(now);
+            },
+            Membership::Part(user) => {
+                self.present.remove(&user);
+                self.heard(now);
+            },
+            Membership::Mode(user, true) => { self.roles.insert(user, Role::Moderator); },
+            Membership::Mode(user, false) => {
+                if self.roles.get(&user) == Some(&Role::Moderator) {
+                    self.roles.remove(&user);
+                }
+            },
+        }
+    }
+
+    // Membership is flowing, so whatever we guessed about the channel being too big was wrong
+    fn heard(&mut self, now: Instant) {
+        self.last_membership = Some(now);
+        self.partial = false;
+    }
+
+    /// Learns from a chat message: its author is present, and their badges tell us their role.
+    pub fn saw_message(&mut self, m: &ChatMessage, now: Instant) {
+        if m.sender.is_empty() {
+            return;
+        }
+        let role = m.badges().iter().map(|(b, _)| match *b {
+            "broadcaster" | "moderator" => Role::Moderator,
+            "vip" => Role::Vip,
+            _ => Role::Viewer,
+        }).min().unwrap_or(Role::Viewer);
+        if role == Role::Viewer {
+            self.roles.remove(&m.sender);
+        } else {
+            self.roles.insert(m.sender.clone(), role);
+        }
+        let last = match self.last_membership {
+            Some(last) => last,
+            None => return,
+        };
+        if self.present.insert(m.sender.clone()) {
+            self.unannounced.push_back((m.sender.clone(), now));
+        }
+        while self.unannounced.front().is_some_and(|(_, t)| now.duration_since(*t) > QUIET_AFTER) {
+            self.unannounced.pop_front();
+        }
+        if now.duration_since(last) >= QUIET_AFTER && self.unannounced.len() > UNANNOUNCED_LIMIT {
+            self.partial = true;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.present.len()
+    }
+
+    fn role(&self, user: &str) -> Role {
+        match self.roles.get(user) {
+            Some(role) => *role,
+            // Past the cutoff NAMES only lists moderators
+            None if self.partial && self.names.contains(user) => Role::Moderator,
+            None => Role::Viewer,
+        }
+    }
+
+    /// Present users whose name contains `filter`, grouped by role and sorted by name.
+    fn grouped(&self, filter: &str) -> Vec<(Role, Vec<&str>)> {
+        let filter = filter.to_lowercase();
+        let mut groups: Vec<(Role, Vec<&str>)> = vec![
+            (Role::Moderator, Vec::new()),
+            (Role::Vip, Vec::new()),
+            (Role::Viewer, Vec::new()),
+        ];
+        for user in self.present.iter().filter(|u| u.contains(&filter)) {
+            let role = self.role(user);
+            groups.iter_mut().find(|(r, _)| *r == role).unwrap().1.push(user);
+        }
+        groups.retain(|(_, users)| !users.is_empty());
+        groups
+    }
+}
+
+/// Full window list of the chatters with a filter box along the bottom.
+pub struct ChatterPanel {
+    pub filter: InputLine,
+    // Index of the first line shown
+    scroll: usize,
+}
+
+impl ChatterPanel {
+    pub fn new() -> Self {
+        Self {
+            filter: InputLine::new(),
+            scroll: 0,
+        }
+    }
+
+    pub fn scroll_by(&mut self, delta: i32, chatters: &Chatters) {
+        // Every user plus a heading per group
+        let max = chatters.len() as i64 + 3;
+        self.scroll = (self.scroll as i64 + delta as i64).clamp(0, max) as usize;
+    }
+
+    pub fn draw(&self, screen: &mut Screen, channel: &str, chatters: &Chatters, theme: &Theme) {
+        let groups = chatters.grouped(self.filter.text());
+        let shown: usize = groups.iter().map(|(_, users)| users.len()).sum();
+
+        screen.fill_row(0, theme.header());
+        let mut header = format!("Chatters in #{}: {} of {}  Esc to close", channel, shown, chatters.len());
+        if chatters.partial {
+            header.push_str("  (large channel, only people seen chatting are listed)");
+        }
+        screen.print_colored(0, 1, &header, theme.text(), theme.header());
+
+        let mut lines: Vec<(String, [f32; 4])> = Vec::new();
+        for (role, users) in &groups {
+            lines.push((format!("{} ({})", role.heading(), users.len()), theme.accent()));
+            lines.extend(users.iter().map(|u| (format!("  {}", u), theme.text())));
+        }
+        if lines.is_empty() {
+            lines.push(("Nobody matches".to_string(), theme.meta()));
+        }
+
+        // Leave the top row for the header and the bottom one for the filter
+        let rows = screen.rows().saturating_sub(2) as usize;
+        let scroll = self.scroll.min(lines.len().saturating_sub(rows));
+        for (row, (line, fg)) in lines.iter().skip(scroll).take(rows).enumerate() {
+            screen.print_colored(row as u32 + 1, 1, line, *fg, theme.background());
+        }
+        self.filter.draw(screen, "filter: ", theme);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(sender: &str, badges: &str) -> ChatMessage {
+        let tags = [("badges".to_string(), badges.to_string())].into_iter().collect();
+        ChatMessage::sent("chan", sender, "hi", tags)
+    }
+
+    fn names(chatters: &mut Chatters, names: &[&str], now: Instant) {
+        chatters.apply(Membership::Names(names.iter().map(|n| n.to_string()).collect()), now);
+        chatters.apply(Membership::EndOfNames, now);
+    }
+
+    // Has `count` new people named `prefix`0 on talk `apart` from each other, starting at `start`
+    fn chat(chatters: &mut Chatters, prefix: &str, count: usize, start: Instant, apart: Duration) -> Instant {
+        let mut now = start;
+        for n in 0..count {
+            now = start + apart * n as u32;
+            chatters.saw_message(&message(&format!("{}{}", prefix, n), ""), now);
+        }
+        now
+    }
+
+    #[test]
+    fn names_merge_with_joins_and_parts() {
+        let now = Instant::now();
+        let mut chatters = Chatters::new();
+        chatters.apply(Membership::Join("early".to_string()), now);
+        names(&mut chatters, &["alice", "bob"], now);
+        assert_eq!(chatters.len(), 3);
+        chatters.apply(Membership::Part("bob".to_string()), now);
+        chatters.apply(Membership::Join("carol".to_string()), now);
+        assert!(chatters.present.iter().eq(["alice", "carol", "early"]));
+    }
+
+    #[test]
+    fn roles_come_from_badges_and_modes() {
+        let now = Instant::now();
+        let mut chatters = Chatters::new();
+        names(&mut chatters, &["alice"], now);
+        chatters.saw_message(&message("bob", "vip/1,subscriber/12"), now);
+        chatters.saw_message(&message("carol", "broadcaster/1"), now);
+        chatters.apply(Membership::Mode("alice".to_string(), true), now);
+        assert_eq!(chatters.role("alice"), Role::Moderator);
+        assert_eq!(chatters.role("bob"), Role::Vip);
+        assert_eq!(chatters.role("carol"), Role::Moderator);
+
+        chatters.apply(Membership::Mode("alice".to_string(), false), now);
+        chatters.saw_message(&message("bob", ""), now);
+        assert_eq!(chatters.role("alice"), Role::Viewer);
+        assert_eq!(chatters.role("bob"), Role::Viewer);
+    }
+
+    #[test]
+    fn talking_before_a_join_is_normal() {
+        let start = Instant::now();
+        let mut chatters = Chatters::new();
+        names(&mut chatters, &[], start);
+        // Well past the limit, but their JOINs keep arriving
+        let mut now = start;
+        for n in 0..30 {
+            now = start + Duration::from_secs(n * 5);
+            chatters.saw_message(&message(&format!("viewer{}", n), ""), now);
+            chatters.apply(Membership::Join(format!("viewer{}", n)), now + Duration::from_secs(8));
+        }
+        chatters.saw_message(&message("late", ""), now + QUIET_AFTER);
+        assert!(!chatters.partial);
+        assert_eq!(chatters.len(), 31);
+    }
+
+    #[test]
+    fn a_few_unannounced_in_a_quiet_channel_is_not_partial() {
+        let start = Instant::now();
+        let mut chatters = Chatters::new();
+        names(&mut chatters, &["alice"], start);
+        // Regulars spread out over an hour without JOINs never pile up in the window
+        chat(&mut chatters, "regular", UNANNOUNCED_LIMIT * 3, start, Duration::from_secs(120));
+        assert!(!chatters.partial);
+    }
+
+    #[test]
+    fn quiet_membership_means_a_partial_list_of_moderators() {
+        let start = Instant::now();
+        let mut chatters = Chatters::new();
+        names(&mut chatters, &["mod1", "mod2"], start);
+        assert_eq!(chatters.role("mod1"), Role::Viewer);
+
+        // Busy straight after joining isn't enough, JOINs may still be on the way
+        let now = chat(&mut chatters, "early", UNANNOUNCED_LIMIT + 1, start, Duration::from_secs(1));
+        assert!(!chatters.partial);
+
+        chat(&mut chatters, "viewer", UNANNOUNCED_LIMIT + 1, now + QUIET_AFTER, Duration::from_millis(100));
+        assert!(chatters.partial);
+        assert_eq!(chatters.role("mod1"), Role::Moderator);
+        assert_eq!(chatters.role("viewer0"), Role::Viewer);
+
+        // Membership turning up again proves otherwise
+        chatters.apply(Membership::Join("someone".to_string()), now + QUIET_AFTER * 2);
+        assert!(!chatters.partial);
+        assert_eq!(chatters.role("mod1"), Role::Viewer);
+    }
+}
@@ -0,0 +1,333 @@
+use std::fs;
+use std::path::PathBuf;
+use crate::completion::InputHistory;
+use crate::input::InputLine;
+use crate::keys;
+use crate::theme::{Theme, THEMES};
+
+// Commands typed after `:`, for running the app rather than chatting:
+//
+//   :join somechannel
+//   :set fontsize=24 nofilters
+//   :theme light
+//   :5down
+//
+// Any action from the keys file also works by name, with an optional count in front.
+
+// History is kept under its own key in an InputHistory
+const HISTORY_KEY: &str = ":";
+const MIN_FONT_SIZE: f32 = 6.0;
+const MAX_FONT_SIZE: f32 = 72.0;
+
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("join", ":join <channel>", "Join another channel's chat"),
+    ("part", ":part [channel]", "Leave a channel, the one being typed in by default"),
+    ("theme", ":theme [name]", "Switch colour theme, or list the themes"),
+    ("set", ":set [option[=value|?|!]|nooption]...", "Change options, or show them all"),
+    ("help", ":help [command]", "List the commands, or explain one"),
+    ("quit", ":quit", "Close eat-chat"),
+];
+
+/// Options `:set` can change while running.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Setting {
+    FontSize,
+    Filters,
+    Spell,
+    PinPaid,
+    Channels,
+    SmoothScroll,
+}
+
+const SETTINGS: &[(&str, Setting, &str)] = &[
+    ("fontsize", Setting::FontSize, "Font size in points"),
+    ("filters", Setting::Filters, "Apply the rules in the filters file"),
+    ("spell", Setting::Spell, "Mark misspelled words while typing"),
+    ("pinpaid", Setting::PinPaid, "Pin Hype Chats above the chat while Twitch does"),
+    ("channels", Setting::Channels, "Label each line with its channel"),
+    ("smoothscroll", Setting::SmoothScroll, "Slide new lines in rather than jumping"),
+];
+
+impl Setting {
+    pub fn name(&self) -> &'static str {
+        SETTINGS.iter().find(|(_, s, _)| s == self).map_or("", |(n, _, _)| *n)
+    }
+
+    // Everything but the font size is on or off
+    fn is_flag(&self) -> bool {
+        *self != Setting::FontSize
+    }
+}
+
+/// What `:set` does to one option.
+#[derive(Copy, Clone)]
+pub enum Change {
+    Show,
+    Turn(bool),
+    Toggle,
+    Value(f32),
+}
+
+pub enum ExCommand {
+    Join(String),
+    Part(Option<String>),
+    /// None lists the themes.
+    Theme(Option<Theme>),
+    Set(Vec<(Setting, Change)>),
+    Help(Option<String>),
+    Action(keys::Action, u32),
+}
+
+impl ExCommand {
+    pub fn parse(line: &str) -> Result<ExCommand, String> {
+        let line = line.trim().trim_start_matches(':');
+        let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (count, line) = line.split_at(digits);
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        if !count.is_empty() && COMMANDS.iter().any(|(n, _, _)| *n == name) {
+            return Err(format!(":{} doesn't take a count", name));
+        }
+        let one_arg = |required: bool| match args.as_slice() {
+            [] if !required => Ok(None),
+            [arg] => Ok(Some(arg.to_string())),
+            _ => Err(format!("Usage: {}", usage(name))),
+        };
+
+        match name {
+            "join" => match channel_name(&one_arg(true)?.unwrap_or_default()) {
+                channel if channel.is_empty() => Err(format!("Usage: {}", usage(name))),
+                channel => Ok(ExCommand::Join(channel)),
+            },
+            "part" => Ok(ExCommand::Part(one_arg(false)?.map(|c| channel_name(&c)))),
+            "theme" => match one_arg(false)? {
+                Some(name) => Theme::find(&name)
+                    .map(|t| ExCommand::Theme(Some(t)))
+                    .ok_or_else(|| format!("No theme called {}, there is {}", name, theme_names().join(", "))),
+                None => Ok(ExCommand::Theme(None)),
+            },
+            "set" if args.is_empty() => Ok(ExCommand::Set(SETTINGS.iter().map(|(_, s, _)| (*s, Change::Show)).collect())),
+            "set" => args.iter().map(|a| parse_setting(a)).collect::<Result<_, _>>().map(ExCommand::Set),
+            "help" | "h" => Ok(ExCommand::Help(one_arg(false)?)),
+            "q" | "quit" => Ok(ExCommand::Action(keys::Action::Quit, 1)),
+            "" => Err("Missing a command".to_string()),
+            _ => match keys::Action::from_name(name) {
+                Some(action) if args.is_empty() => Ok(ExCommand::Action(action, count.parse().unwrap_or(1))),
+                Some(_) => Err(format!(":{} doesn't take arguments", name)),
+                None => Err(format!("Not a command: {}, see :help", name)),
+            },
+        }
+    }
+}
+
+fn channel_name(s: &str) -> String {
+    s.trim_start_matches('#').to_lowercase()
+}
+
+fn usage(name: &str) -> &'static str {
+    COMMANDS.iter().find(|(n, _, _)| *n == name).map_or("", |(_, u, _)| *u)
+}
+
+fn theme_names() -> Vec<&'static str> {
+    THEMES.iter().map(|t| t.name).collect()
+}
+
+/// One `:set` argument, vim style: `name` turns on or shows, `noname` turns off, `name!` toggles,
+/// `name?` shows and `name=value` sets.
+fn parse_setting(arg: &str) -> Result<(Setting, Change), String> {
+    let find = |name: &str| SETTINGS.iter().find(|(n, _, _)| *n == name).map(|(_, s, _)| *s);
+    let unknown = || format!("No option called {}", arg);
+
+    if let Some((name, value)) = arg.split_once('=') {
+        let setting = find(name).ok_or_else(unknown)?;
+        if setting.is_flag() {
+            return Err(format!("{} is on or off, use {} or no{}", name, name, name));
+        }
+        return match value.parse::<f32>() {
+            Ok(size) if (MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&size) => Ok((setting, Change::Value(size))),
+            _ => Err(format!("fontsize is a number from {} to {}", MIN_FONT_SIZE, MAX_FONT_SIZE)),
+        };
+    }
+    if let Some(name) = arg.strip_suffix('?') {
+        return Ok((find(name).ok_or_else(unknown)?, Change::Show));
+    }
+    let flag = |name: &str| find(name).filter(Setting::is_flag);
+    if let Some(setting) = arg.strip_suffix('!').and_then(flag) {
+        return Ok((setting, Change::Toggle));
+    }
+    if let Some(setting) = arg.strip_prefix("no").and_then(flag) {
+        return Ok((setting, Change::Turn(false)));
+    }
+    match find(arg) {
+        Some(setting) if setting.is_flag() => Ok((setting, Change::Turn(true))),
+        Some(setting) => Ok((setting, Change::Show)),
+        None => Err(unknown()),
+    }
+}
+
+/// Lines for `:help`, or `:help command`.
+pub fn help(topic: Option<&str>) -> Result<Vec<String>, String> {
+    let topic = match topic {
+        None => {
+            let mut lines: Vec<String> = COMMANDS.iter().map(|(_, usage, about)| format!("{:<40} {}", usage, about)).collect();
+            lines.push("Any action from the keys file runs by name too, with a count first, e.g. :5down".to_string());
+            return Ok(lines);
+        },
+        Some(topic) => topic.trim_start_matches(':'),
+    };
+    match COMMANDS.iter().find(|(n, _, _)| *n == topic) {
+        Some((_, usage, about)) => {
+            let mut lines = vec![format!("{:<40} {}", usage, about)];
+            match topic {
+                "set" => lines.extend(SETTINGS.iter().map(|(name, _, about)| format!("  {:<38} {}", name, about))),
+                "theme" => lines.push(format!("  Themes: {}", theme_names().join(", "))),
+                _ => {},
+            }
+            Ok(lines)
+        },
+        None if keys::Action::from_name(topic).is_some() => Ok(vec![format!(":[count]{}  Runs the {} action", topic, topic)]),
+        None => Err(format!("No help for {}", topic)),
+    }
+}
+
+/// What the word before the cursor could complete to, and where that word starts. The first
+/// word is a command, later ones depend on it.
+pub fn candidates(before_cursor: &str, channels: &[String]) -> (usize, Vec<String>) {
+    let start = before_cursor.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &before_cursor[start..];
+    let command = before_cursor[..start].split_whitespace().next();
+    let options: Vec<String> = match command {
+        None => COMMANDS.iter().map(|(n, _, _)| *n).chain(keys::Action::names()).map(String::from).collect(),
+        Some("part") => channels.to_vec(),
+        Some("theme") => theme_names().into_iter().map(String::from).collect(),
+        Some("set") => SETTINGS.iter()
+            .flat_map(|(name, setting, _)| {
+                let off = setting.is_flag().then(|| format!("no{}", name));
+                std::iter::once(name.to_string()).chain(off)
+            })
+            .collect(),
+        Some("help" | "h") => COMMANDS.iter().map(|(n, _, _)| n.to_string()).collect(),
+        Some(_) => Vec::new(),
+    };
+    let candidates = options.into_iter()
+        .filter(|o| o.starts_with(word) && o != word)
+        .map(|o| format!("{} ", o))
+        .collect();
+    (start, candidates)
+}
+
+/// Commands run before, saved so they come back with Up after a restart.
+pub struct CommandHistory {
+    history: InputHistory,
+    path: PathBuf,
+}
+
+impl CommandHistory {
+    pub fn load(path: PathBuf) -> Self {
+        let mut history = InputHistory::new();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines() {
+                history.record(HISTORY_KEY, line);
+            }
+        }
+        Self { history, path }
+    }
+
+    pub fn record(&mut self, line: &str) {
+        self.history.record(HISTORY_KEY, line);
+        let mut contents = self.history.lines(HISTORY_KEY).join("\n");
+        contents.push('\n');
+        let saved = match self.path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&self.path, contents)),
+            None => fs::write(&self.path, contents),
+        };
+        if let Err(e) = saved {
+            println!("Failed to save command history to {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn step(&mut self, delta: i32, input: &mut InputLine) {
+        self.history.step(HISTORY_KEY, delta, input);
+    }
+
+    pub fn reset(&mut self) {
+        self.history.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(line: &str) -> Vec<(Setting, Change)> {
+        match ExCommand::parse(&format!("set {}", line)) {
+            Ok(ExCommand::Set(settings)) => settings,
+            _ => panic!("{} isn't a :set", line),
+        }
+    }
+
+    #[test]
+    fn commands_take_their_arguments() {
+        assert!(matches!(ExCommand::parse(":join #SomeChannel"), Ok(ExCommand::Join(c)) if c == "somechannel"));
+        assert!(ExCommand::parse("join").is_err());
+        assert!(ExCommand::parse("join one two").is_err());
+        assert!(matches!(ExCommand::parse("part"), Ok(ExCommand::Part(None))));
+        assert!(matches!(ExCommand::parse("theme LIGHT"), Ok(ExCommand::Theme(Some(t))) if t.name == "light"));
+        assert!(ExCommand::parse("theme nope").is_err());
+        assert!(matches!(ExCommand::parse("q"), Ok(ExCommand::Action(keys::Action::Quit, 1))));
+        assert!(ExCommand::parse("").is_err());
+        assert!(ExCommand::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn counts_only_go_on_actions() {
+        assert!(matches!(ExCommand::parse("5down"), Ok(ExCommand::Action(keys::Action::Down, 5))));
+        assert!(matches!(ExCommand::parse("down"), Ok(ExCommand::Action(keys::Action::Down, 1))));
+        assert!(ExCommand::parse("down 5").is_err());
+        assert_eq!(ExCommand::parse("5join x").err().unwrap(), ":join doesn't take a count");
+    }
+
+    #[test]
+    fn set_reads_vim_style_options() {
+        assert!(matches!(set("nofilters")[..], [(Setting::Filters, Change::Turn(false))]));
+        assert!(matches!(set("spell")[..], [(Setting::Spell, Change::Turn(true))]));
+        assert!(matches!(set("spell!")[..], [(Setting::Spell, Change::Toggle)]));
+        assert!(matches!(set("pinpaid?")[..], [(Setting::PinPaid, Change::Show)]));
+        assert!(matches!(set("fontsize")[..], [(Setting::FontSize, Change::Show)]));
+        assert!(matches!(set("fontsize=24 nochannels")[..],
+            [(Setting::FontSize, Change::Value(size)), (Setting::Channels, Change::Turn(false))] if size == 24.0));
+        assert_eq!(set("").len(), SETTINGS.len());
+        assert!(set("").iter().all(|(_, change)| matches!(change, Change::Show)));
+    }
+
+    #[test]
+    fn set_rejects_what_doesnt_fit() {
+        assert!(ExCommand::parse("set fontsize=100").is_err());
+        assert!(ExCommand::parse("set fontsize=big").is_err());
+        assert!(ExCommand::parse("set filters=1").is_err());
+        assert!(ExCommand::parse("set nofontsize").is_err());
+        assert!(ExCommand::parse("set fontsize!").is_err());
+        assert!(ExCommand::parse("set bogus").is_err());
+    }
+
+    #[test]
+    fn completes_by_position() {
+        let channels = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(candidates("jo", &channels), (0, vec!["join ".to_string()]));
+        assert!(candidates("se", &channels).1.contains(&"set ".to_string()));
+        let (start, options) = candidates("set nof", &channels);
+        assert_eq!(start, 4);
+        assert_eq!(options, ["nofilters "]);
+        assert_eq!(candidates("part t", &channels), (5, vec!["two ".to_string()]));
+        assert!(candidates("join x", &channels).1.is_empty());
+    }
+
+    #[test]
+    fn help_knows_commands_and_actions() {
+        assert_eq!(help(None).unwrap().len(), COMMANDS.len() + 1);
+        assert_eq!(help(Some(":set")).unwrap().len(), SETTINGS.len() + 1);
+        assert!(help(Some("half_page_down")).is_ok());
+        assert!(help(Some("nothing")).is_err());
+    }
+}
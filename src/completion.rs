@@ -0,0 +1,214 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use crate::chat::ChatMessage;
+use crate::input::InputLine;
+use crate::moderation;
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+// Sent lines remembered per channel for Up/Down
+const HISTORY_LIMIT: usize = 100;
+// Recent speakers remembered per channel for @nick completion
+const SPEAKER_LIMIT: usize = 500;
+// Candidates shown in the popup at once
+const POPUP_ROWS: usize = 8;
+
+/// Lines sent to each channel, recalled with Up and Down like a shell.
+pub struct InputHistory {
+    sent: HashMap<String, VecDeque<String>>,
+    // How far back we are while browsing, and what was typed before we started
+    position: Option<usize>,
+    draft: String,
+}
+
+impl InputHistory {
+    pub fn new() -> Self {
+        Self {
+            sent: HashMap::new(),
+            position: None,
+            draft: String::new(),
+        }
+    }
+
+    pub fn record(&mut self, channel: &str, line: &str) {
+        self.position = None;
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        let sent = self.sent.entry(channel.to_string()).or_default();
+        // Sending the same thing twice only needs one Up to get back to it
+        if sent.back().map(String::as_str) != Some(line) {
+            sent.push_back(line.to_string());
+        }
+        if sent.len() > HISTORY_LIMIT {
+            sent.pop_front();
+        }
+    }
+
+    /// What was sent to `channel`, oldest first.
+    pub fn lines(&self, channel: &str) -> Vec<&str> {
+        self.sent.get(channel).map_or(Vec::new(), |sent| sent.iter().map(String::as_str).collect())
+    }
+
+    /// Stops browsing, e.g. when the channel being typed in changes.
+    pub fn reset(&mut self) {
+        self.position = None;
+    }
+
+    /// Steps back through what was sent to `channel`, or forward for a positive `delta`, putting
+    /// the line into `input`. Stepping forward past the newest brings back what was being typed.
+    pub fn step(&mut self, channel: &str, delta: i32, input: &mut InputLine) {
+        let sent = match self.sent.get(channel) {
+            Some(sent) if !sent.is_empty() => sent,
+            _ => return,
+        };
+        let current = self.position.unwrap_or(sent.len());
+        if self.position.is_none() {
+            self.draft = input.text().to_string();
+        }
+        let next = (current as i64 + delta as i64).clamp(0, sent.len() as i64) as usize;
+        if next == sent.len() {
+            self.position = None;
+            input.set_text(&self.draft);
+        } else {
+            self.position = Some(next);
+            input.set_text(&sent[next]);
+        }
+    }
+}
+
+/// What Tab can complete to: people who spoke recently, emotes seen in chat and slash commands.
+///
+/// The emotes are a stand-in for the ones we can actually use. Those are our emote sets, which
+/// GLOBALUSERSTATE lists by id, but turning ids into codes needs the Helix API and a client id we
+/// don't have. So only emotes someone has already used in chat complete, including ones we may
+/// not be subscribed to.
+pub struct Completer {
+    // Most recent speaker last
+    speakers: HashMap<String, VecDeque<String>>,
+    // Emote codes seen in chat, in place of our own emote sets
+    emotes: BTreeSet<String>,
+}
+
+impl Completer {
+    pub fn new() -> Self {
+        Self {
+            speakers: HashMap::new(),
+            emotes: BTreeSet::new(),
+        }
+    }
+
+    pub fn saw_message(&mut self, m: &ChatMessage) {
+        if m.sender.is_empty() {
+            return;
+        }
+        // Complete to the name people see, unless it's one of the localised ones that can't be typed
+        // as a mention
+        let name = m.display_name();
+        let name = if name.eq_ignore_ascii_case(&m.sender) { name } else { &m.sender };
+        let speakers = self.speakers.entry(m.channel.clone()).or_default();
+        if let Some(i) = speakers.iter().position(|s| s == name) {
+            speakers.remove(i);
+        }
+        speakers.push_back(name.to_string());
+        if speakers.len() > SPEAKER_LIMIT {
+            speakers.pop_front();
+        }
+
+        let chars: Vec<char> = m.message.chars().collect();
+        for emote in m.emotes() {
+            if let Some(code) = chars.get(emote.range) {
+                self.emotes.insert(code.iter().collect());
+            }
+        }
+    }
+
+    /// Everything `word` could complete to, best first. A word at the start of the line starting
+    /// with `/` is a command, one starting with `@` a nick, and anything else an emote or a nick.
+    fn candidates(&self, channel: &str, word: &str, line_start: bool) -> Vec<String> {
+        let matches = |candidate: &str, prefix: &str| candidate.to_lowercase().starts_with(&prefix.to_lowercase());
+        let speakers = self.speakers.get(channel).into_iter().flat_map(|s| s.iter().rev());
+
+        if let Some(prefix) = word.strip_prefix('/').filter(|_| line_start) {
+            return moderation::NAMES.iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| format!("/{} ", name))
+                .collect();
+        }
+        if let Some(prefix) = word.strip_prefix('@') {
+            return speakers.filter(|s| matches(s, prefix)).map(|s| format!("@{} ", s)).collect();
+        }
+        if word.is_empty() {
+            return Vec::new();
+        }
+        let emotes = self.emotes.iter().filter(|e| matches(e, word)).map(|e| format!("{} ", e));
+        emotes.chain(speakers.filter(|s| matches(s, word)).map(|s| format!("{} ", s))).collect()
+    }
+}
+
+/// Tab cycling through the candidates for the word before the cursor, with a popup listing them.
+pub struct Completion {
+    // Byte offset in the input where the completed word starts
+    start: usize,
+    original: String,
+    candidates: Vec<String>,
+    selected: usize,
+}
+
+impl Completion {
+    /// Starts completing the word before the cursor, filling in the first candidate. None when
+    /// there is nothing to complete to.
+    pub fn start(input: &mut InputLine, completer: &Completer, channel: &str) -> Option<Self> {
+        let (start, word) = input.word_before_cursor();
+        let candidates = completer.candidates(channel, word, input.text()[..start].trim().is_empty());
+        Self::with_candidates(input, start, candidates)
+    }
+
+    /// Offers `candidates` for the text between `start` and the cursor, e.g. spelling suggestions.
+    pub fn with_candidates(input: &mut InputLine, start: usize, candidates: Vec<String>) -> Option<Self> {
+        let first = candidates.first()?.clone();
+        let completion = Self {
+            start,
+            original: input.text()[start..input.cursor()].to_string(),
+            candidates,
+            selected: 0,
+        };
+        input.replace_before_cursor(start, &first);
+        Some(completion)
+    }
+
+    /// Moves to the next candidate, or the previous for a negative `delta`, and fills it in.
+    pub fn cycle(&mut self, delta: i32, input: &mut InputLine) {
+        let len = self.candidates.len() as i32;
+        self.selected = (self.selected as i32 + delta).rem_euclid(len) as usize;
+        input.replace_before_cursor(self.start, &self.candidates[self.selected]);
+    }
+
+    /// Puts back what was typed before completing.
+    pub fn cancel(&self, input: &mut InputLine) {
+        input.replace_before_cursor(self.start, &self.original);
+    }
+
+    /// Draws the candidates above the input line, whose top row is `bottom`.
+    pub fn draw(&self, screen: &mut Screen, bottom: u32, theme: &Theme) {
+        let rows = POPUP_ROWS.min(bottom as usize);
+        if rows == 0 {
+            return;
+        }
+        // Scroll the list so the selected candidate is always in it
+        let first = (self.selected + 1).saturating_sub(rows);
+        let width = self.candidates.iter().map(|c| c.trim_end().chars().count()).max().unwrap_or(0) + 2;
+        let shown = self.candidates.len().min(rows);
+        let top = bottom - shown as u32;
+        for (n, candidate) in self.candidates.iter().enumerate().skip(first).take(shown) {
+            let row = top + (n - first) as u32;
+            let bg = if n == self.selected { theme.cursor() } else { theme.panel() };
+            screen.fill(row, 1, width as u32, bg);
+            screen.print_colored(row, 2, candidate.trim_end(), theme.text(), bg);
+        }
+        if self.candidates.len() > shown {
+            let more = format!(" {}/{} ", self.selected + 1, self.candidates.len());
+            screen.print_colored(top, 1 + width as u32, &more, theme.meta(), theme.panel());
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Directory holding user editable configuration, `$XDG_CONFIG_HOME/eat-chat` falling back to
+/// `~/.config/eat-chat`.
+pub fn config_dir() -> PathBuf {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir().join(".config"),
+    };
+    base.join("eat-chat")
+}
+
+/// Directory for things we accumulate rather than the user edits, `$XDG_DATA_HOME/eat-chat`
+/// falling back to `~/.local/share/eat-chat`.
+pub fn data_dir() -> PathBuf {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir().join(".local").join("share"),
+    };
+    base.join("eat-chat")
+}
+
+fn home_dir() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."))
+}
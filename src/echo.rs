@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use rand::Rng;
+use crate::chat::ChatMessage;
+use crate::template::Template;
+use crate::view::{ChatView, ERROR, ERROR_BG, NORMAL};
+
+// Twitch answers every message with a USERSTATE or a NOTICE within a second or two, anything
+// still waiting after this was lost
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(15);
+
+const PENDING_FG: [f32; 4] = [0.6, 0.6, 0.65, 1.0];
+
+/// A message we sent that Twitch hasn't answered yet.
+struct Pending {
+    nonce: String,
+    channel: String,
+    text: String,
+    // Tags we sent it with, e.g. what it replies to
+    tags: HashMap<String, String>,
+    // The line showing it in the chat view
+    entry: usize,
+    sent_at: Instant,
+}
+
+/// Shows what we send straight away, then settles each line once Twitch accepts or rejects it.
+pub struct LocalEcho {
+    nick: String,
+    pending: Vec<Pending>,
+}
+
+impl LocalEcho {
+    pub fn new(nick: &str) -> Self {
+        Self {
+            nick: nick.to_string(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// A fresh `client-nonce` to tag a message with, Twitch hands it back in the USERSTATE.
+    pub fn nonce() -> String {
+        format!("{:032x}", rand::thread_rng().gen::<u128>())
+    }
+
+    /// Shows a message that has just gone out, in the pending style.
+    pub fn sent(&mut self, nonce: String, channel: &str, text: &str, tags: HashMap<String, String>, view: &mut ChatView, template: &Template) {
+        let m = ChatMessage::sent(channel, &self.nick, text, tags.clone());
+        let line = template.render(&m);
+        let entry = view.push_message(m, line, PENDING_FG);
+        self.pending.push(Pending {
+            nonce,
+            channel: channel.to_string(),
+            text: text.to_string(),
+            tags,
+            entry,
+            sent_at: Instant::now(),
+        });
+    }
+
+    /// Settles a pending message from the USERSTATE Twitch sent for it, redrawing it with our real
+    /// colour and badges. Returns the message as it went out, None when nothing was waiting.
+    pub fn confirm(&mut self, channel: &str, tags: HashMap<String, String>, view: &mut ChatView, template: &Template) -> Option<ChatMessage> {
+        // USERSTATE also comes on joining, only the ones answering a message carry its id
+        if tags.get("id").is_none_or(|id| id.is_empty()) {
+            return None;
+        }
+        // Match on the nonce when Twitch gives it back, otherwise answers come in order
+        let i = tags.get("client-nonce")
+            .and_then(|nonce| self.pending.iter().position(|p| p.nonce == *nonce))
+            .or_else(|| self.pending.iter().position(|p| p.channel == channel))?;
+        let mut pending = self.pending.remove(i);
+        pending.tags.extend(tags);
+        let m = ChatMessage::sent(&pending.channel, &self.nick, &pending.text, pending.tags);
+        view.replace(pending.entry, m.clone(), template.render(&m), NORMAL);
+        Some(m)
+    }
+
+    /// Marks the oldest pending message in `channel` as failed because of a NOTICE, e.g. slow
+    /// mode. Returns false when nothing was waiting, so the NOTICE is about something else.
+    pub fn reject(&mut self, channel: &str, reason: &str, view: &mut ChatView, template: &Template) -> bool {
+        match self.pending.iter().position(|p| p.channel == channel) {
+            Some(i) => {
+                let pending = self.pending.remove(i);
+                self.fail(pending, reason, view, template);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Gives up on messages Twitch never answered. Returns true if any were.
+    pub fn expire(&mut self, now: Instant, view: &mut ChatView, template: &Template) -> bool {
+        let (expired, pending) = self.pending.drain(..).partition(|p| now.duration_since(p.sent_at) >= CONFIRM_TIMEOUT);
+        self.pending = pending;
+        let any = !expired.is_empty();
+        for p in expired {
+            self.fail(p, "no answer from Twitch", view, template);
+        }
+        any
+    }
+
+    fn fail(&self, pending: Pending, reason: &str, view: &mut ChatView, template: &Template) {
+        let m = ChatMessage::sent(&pending.channel, &self.nick, &pending.text, pending.tags);
+        let mut line = template.render(&m);
+        line.text.push_str(&format!("  ✗ {}", reason));
+        view.replace_colored(pending.entry, m, line, ERROR, ERROR_BG);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn tags(tags: &[(&str, &str)]) -> HashMap<String, String> {
+        tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    struct Setup {
+        echo: LocalEcho,
+        view: ChatView,
+        template: Template,
+    }
+
+    impl Setup {
+        fn new() -> Self {
+            Self {
+                echo: LocalEcho::new("Me"),
+                view: ChatView::new(),
+                template: Template::load(Path::new("/nonexistent/template")),
+            }
+        }
+
+        fn send(&mut self, nonce: &str, channel: &str, text: &str) {
+            self.echo.sent(nonce.to_string(), channel, text, tags(&[("client-nonce", nonce)]), &mut self.view, &self.template);
+        }
+
+        fn confirm(&mut self, channel: &str, answer: &[(&str, &str)]) -> Option<String> {
+            self.echo.confirm(channel, tags(answer), &mut self.view, &self.template).map(|m| m.message)
+        }
+
+        fn pending(&self) -> Vec<&str> {
+            self.echo.pending.iter().map(|p| p.text.as_str()).collect()
+        }
+    }
+
+    #[test]
+    fn nonces_are_fresh() {
+        let nonce = LocalEcho::nonce();
+        assert_eq!(nonce.len(), 32);
+        assert!(nonce.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(nonce, LocalEcho::nonce());
+    }
+
+    #[test]
+    fn nonce_picks_the_message_out_of_order() {
+        let mut s = Setup::new();
+        s.send("n1", "chan", "first");
+        s.send("n2", "chan", "second");
+        assert_eq!(s.confirm("chan", &[("id", "a"), ("client-nonce", "n2")]).as_deref(), Some("second"));
+        assert_eq!(s.pending(), ["first"]);
+    }
+
+    #[test]
+    fn without_a_nonce_answers_come_in_order() {
+        let mut s = Setup::new();
+        s.send("n1", "one", "first");
+        s.send("n2", "two", "second");
+        s.send("n3", "two", "third");
+        assert_eq!(s.confirm("two", &[("id", "a")]).as_deref(), Some("second"));
+        // A nonce we never sent falls back to order too
+        assert_eq!(s.confirm("two", &[("id", "b"), ("client-nonce", "other")]).as_deref(), Some("third"));
+        assert_eq!(s.confirm("two", &[("id", "c")]), None);
+        assert_eq!(s.pending(), ["first"]);
+    }
+
+    #[test]
+    fn joining_userstate_confirms_nothing() {
+        let mut s = Setup::new();
+        s.send("n1", "chan", "first");
+        assert_eq!(s.confirm("chan", &[("color", "#FF0000")]), None);
+        assert_eq!(s.confirm("chan", &[("id", ""), ("client-nonce", "n1")]), None);
+        assert_eq!(s.pending(), ["first"]);
+    }
+
+    #[test]
+    fn confirming_redraws_with_our_tags() {
+        let mut s = Setup::new();
+        s.echo.sent("n1".to_string(), "chan", "hi", tags(&[("reply-parent-msg-id", "p")]), &mut s.view, &s.template);
+        s.confirm("chan", &[("id", "a"), ("color", "#FF0000"), ("badges", "vip/1")]);
+        s.view.move_cursor(-1);
+        let m = s.view.cursor_message().unwrap();
+        assert_eq!(m.id, "a");
+        assert_eq!(m.tag("reply-parent-msg-id"), Some("p"));
+        assert_eq!(m.color(), [255, 0, 0]);
+        assert_eq!(m.sender, "me");
+    }
+
+    #[test]
+    fn notices_reject_the_oldest_in_their_channel() {
+        let mut s = Setup::new();
+        s.send("n1", "one", "first");
+        s.send("n2", "two", "second");
+        s.send("n3", "two", "third");
+        assert!(s.echo.reject("two", "slow mode", &mut s.view, &s.template));
+        assert_eq!(s.pending(), ["first", "third"]);
+        assert!(!s.echo.reject("three", "slow mode", &mut s.view, &s.template));
+        assert_eq!(s.pending(), ["first", "third"]);
+    }
+
+    #[test]
+    fn unanswered_messages_expire() {
+        let mut s = Setup::new();
+        s.send("n1", "chan", "first");
+        let now = Instant::now();
+        assert!(!s.echo.expire(now, &mut s.view, &s.template));
+        assert_eq!(s.pending(), ["first"]);
+        assert!(s.echo.expire(now + CONFIRM_TIMEOUT, &mut s.view, &s.template));
+        assert!(s.pending().is_empty());
+        assert!(!s.echo.expire(now + CONFIRM_TIMEOUT, &mut s.view, &s.template));
+    }
+}
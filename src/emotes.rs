@@ -0,0 +1,111 @@
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An emote occurrence within a message, as described by the `emotes` tag.
+pub struct EmoteRef {
+    pub id: String,
+    /// Char range of the emote code within the message text.
+    pub range: Range<usize>,
+}
+
+/// Parses an `emotes` tag such as `25:0-4,12-16/1902:6-10`, returning occurrences in the order
+/// they appear in the message.
+pub fn parse(tag: &str) -> Vec<EmoteRef> {
+    let mut emotes = Vec::new();
+    for emote in tag.split('/') {
+        let (id, positions) = match emote.split_once(':') {
+            Some(e) => e,
+            None => continue,
+        };
+        for position in positions.split(',') {
+            let (start, end) = match position.split_once('-') {
+                Some(p) => p,
+                None => continue,
+            };
+            if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) {
+                emotes.push(EmoteRef {
+                    id: id.to_string(),
+                    range: start..end + 1,
+                });
+            }
+        }
+    }
+    emotes.sort_by_key(|e| e.range.start);
+    emotes
+}
+
+pub fn url(id: &str) -> String {
+    format!("https://static-cdn.jtvnw.net/emoticons/v2/{}/default/dark/1.0", id)
+}
+
+/// Guesses an image's mime type from its first bytes.
+pub fn mime_type(image: &[u8]) -> &'static str {
+    if image.starts_with(b"GIF8") {
+        "image/gif"
+    } else if image.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if image.len() > 12 && &image[8..12] == b"WEBP" {
+        "image/webp"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Downloads emote images from Twitch's CDN, keeping a copy on disk so each one is only fetched
+/// once.
+pub struct EmoteFetcher {
+    agent: Option<ureq::Agent>,
+    cache_dir: PathBuf,
+}
+
+impl EmoteFetcher {
+    pub fn new() -> Self {
+        let agent = match native_tls::TlsConnector::new() {
+            Ok(tls) => Some(ureq::AgentBuilder::new()
+                .tls_connector(Arc::new(tls))
+                .timeout(Duration::from_secs(10))
+                .build()),
+            Err(e) => {
+                println!("No TLS available, emotes will not be downloaded: {}", e);
+                None
+            }
+        };
+        Self {
+            agent,
+            cache_dir: crate::config::data_dir().join("emotes"),
+        }
+    }
+
+    pub fn fetch(&self, id: &str) -> Option<Vec<u8>> {
+        // Ids are alphanumeric with underscores, never let one escape the cache dir
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return None;
+        }
+        let path = self.cache_dir.join(id);
+        if let Ok(image) = fs::read(&path) {
+            return Some(image);
+        }
+
+        let response = match self.agent.as_ref()?.get(&url(id)).call() {
+            Ok(r) => r,
+            Err(e) => {
+                println!("Failed to fetch emote {}: {}", id, e);
+                return None;
+            }
+        };
+        let mut image = Vec::new();
+        if let Err(e) = response.into_reader().read_to_end(&mut image) {
+            println!("Failed to read emote {}: {}", id, e);
+            return None;
+        }
+
+        if fs::create_dir_all(&self.cache_dir).and_then(|_| fs::write(&path, &image)).is_err() {
+            println!("Failed to cache emote {} at {}", id, path.display());
+        }
+        Some(image)
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use base64::Engine;
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use serde_json::json;
+use crate::chat::{ChatMessage, MessageKind};
+use crate::emotes::{self, EmoteFetcher};
+use crate::history::{self, History};
+use crate::results::local_time;
+
+const USAGE: &str = "usage: eat-chat export <channel> <from> <to> [--format html|json] [--output FILE]
+
+<from> and <to> are local times, either `HH:MM` for today or `YYYY-MM-DD HH:MM`.";
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Html,
+    Json,
+}
+
+struct ExportArgs {
+    channel: String,
+    from: i64,
+    to: i64,
+    format: Format,
+    output: PathBuf,
+}
+
+fn parse_args(args: &[String]) -> Result<ExportArgs, String> {
+    let mut positional = Vec::new();
+    let mut format = Format::Html;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" | "-f" => format = match args.next().map(String::as_str) {
+                Some("html") => Format::Html,
+                Some("json") => Format::Json,
+                other => return Err(format!("unknown format {:?}", other.unwrap_or(""))),
+            },
+            "--output" | "-o" => output = Some(PathBuf::from(args.next().ok_or("missing output file")?)),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let (channel, from, to) = match positional[..] {
+        [channel, from, to] => (channel.trim_start_matches('#').to_lowercase(), from, to),
+        _ => return Err(USAGE.to_string()),
+    };
+    let from = parse_time(from).ok_or_else(|| format!("can't understand time {:?}", from))?;
+    let to = parse_time(to).ok_or_else(|| format!("can't understand time {:?}", to))?;
+    if to <= from {
+        return Err("the end of the range must come after the start".to_string());
+    }
+
+    let output = output.unwrap_or_else(|| {
+        let extension = if format == Format::Html { "html" } else { "json" };
+        PathBuf::from(format!("{}-{}.{}", channel, local_time(from, "%Y%m%d-%H%M"), extension))
+    });
+
+    Ok(ExportArgs { channel, from, to, format, output })
+}
+
+/// Parses a local time into milliseconds since the epoch.
+fn parse_time(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let datetime = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| {
+            let time = ["%H:%M:%S", "%H:%M"].iter().find_map(|f| NaiveTime::parse_from_str(s, f).ok())?;
+            Some(Local::now().date_naive().and_time(time))
+        })
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))?;
+    Local.from_local_datetime(&datetime).earliest().map(|t| t.timestamp_millis())
+}
+
+/// Entry point for `eat-chat export ...`, writes the transcript and reports where it went.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args)?;
+    let history = History::open(&history::default_path()).map_err(|e| e.to_string())?;
+    let messages = history.range(&args.channel, args.from, args.to).map_err(|e| e.to_string())?;
+
+    let document = match args.format {
+        Format::Html => to_html(&args, &messages, &EmoteFetcher::new()),
+        Format::Json => to_json(&args, &messages),
+    };
+    fs::write(&args.output, document).map_err(|e| format!("{}: {}", args.output.display(), e))?;
+    println!("Exported {} messages from #{} to {}", messages.len(), args.channel, args.output.display());
+    Ok(())
+}
+
+fn to_json(args: &ExportArgs, messages: &[ChatMessage]) -> String {
+    let messages: Vec<_> = messages.iter().map(|m| {
+        let text: Vec<char> = m.message.chars().collect();
+        let [r, g, b] = m.color();
+        json!({
+            "id": m.id,
+            "time": local_time(m.timestamp, "%Y-%m-%dT%H:%M:%S%.3f%:z"),
+            "ts": m.timestamp,
+            "kind": m.kind.name(),
+            "sender": m.sender,
+            "display_name": m.display_name(),
+            "color": format!("#{:02X}{:02X}{:02X}", r, g, b),
+            "badges": m.badges().iter().map(|(name, version)| json!({"name": name, "version": version})).collect::<Vec<_>>(),
+            "text": m.message,
+            "emotes": m.emotes().iter().map(|e| json!({
+                "id": e.id,
+                "code": text.get(e.range.clone()).map(|c| c.iter().collect::<String>()),
+                "start": e.range.start,
+                "end": e.range.end,
+            })).collect::<Vec<_>>(),
+            "tags": m.tags,
+        })
+    }).collect();
+
+    serde_json::to_string_pretty(&json!({
+        "channel": args.channel,
+        "from": local_time(args.from, "%Y-%m-%dT%H:%M:%S%:z"),
+        "to": local_time(args.to, "%Y-%m-%dT%H:%M:%S%:z"),
+        "messages": messages,
+    })).unwrap_or_default()
+}
+
+const STYLE: &str = "
+body { background: #18181b; color: #efeff1; font: 14px/1.5 sans-serif; margin: 2em; }
+h1 { font-size: 18px; }
+.msg { padding: 2px 0; }
+.time { color: #8a8a8f; font-family: monospace; margin-right: 0.5em; }
+.badge { font-size: 10px; border-radius: 3px; padding: 0 4px; margin-right: 3px; background: #3a3a3d; vertical-align: middle; }
+.badge-broadcaster { background: #e91916; }
+.badge-moderator { background: #00ad03; }
+.badge-vip { background: #e005b9; }
+.badge-subscriber { background: #8205b4; }
+.name { font-weight: bold; }
+.notice { color: #adadb8; font-style: italic; }
+.action .text { font-style: italic; }
+img.emote { vertical-align: middle; height: 28px; }
+";
+
+fn to_html(args: &ExportArgs, messages: &[ChatMessage], fetcher: &EmoteFetcher) -> String {
+    let mut images: HashMap<String, Option<String>> = HashMap::new();
+    let mut body = String::new();
+
+    for m in messages {
+        let class = match m.kind {
+            MessageKind::Chat => "msg",
+            MessageKind::Action => "msg action",
+            MessageKind::Notice | MessageKind::UserNotice => "msg notice",
+        };
+        body.push_str(&format!(
+            "<div class=\"{}\"><span class=\"time\">{}</span>",
+            class,
+            local_time(m.timestamp, "%H:%M:%S"),
+        ));
+        for (badge, _) in m.badges() {
+            body.push_str(&format!("<span class=\"badge badge-{0}\">{0}</span>", escape(badge)));
+        }
+        if !m.sender.is_empty() {
+            let [r, g, b] = m.color();
+            body.push_str(&format!(
+                "<span class=\"name\" style=\"color: #{:02X}{:02X}{:02X}\">{}</span>{}",
+                r, g, b,
+                escape(m.display_name()),
+                if m.kind == MessageKind::Action { " " } else { ": " },
+            ));
+        }
+
+        // Splice emote images into the text wherever the emotes tag points
+        body.push_str("<span class=\"text\">");
+        let text: Vec<char> = m.message.chars().collect();
+        let mut pos = 0;
+        for emote in m.emotes() {
+            if emote.range.start < pos || emote.range.end > text.len() {
+                continue;
+            }
+            body.push_str(&escape(&text[pos..emote.range.start].iter().collect::<String>()));
+            let code = escape(&text[emote.range.clone()].iter().collect::<String>());
+            let image = images.entry(emote.id.clone()).or_insert_with(|| {
+                fetcher.fetch(&emote.id).map(|image| format!(
+                    "data:{};base64,{}",
+                    emotes::mime_type(&image),
+                    base64::engine::general_purpose::STANDARD.encode(&image),
+                ))
+            });
+            match image {
+                Some(src) => body.push_str(&format!("<img class=\"emote\" src=\"{}\" alt=\"{1}\" title=\"{1}\">", src, code)),
+                None => body.push_str(&code),
+            }
+            pos = emote.range.end;
+        }
+        body.push_str(&escape(&text[pos.min(text.len())..].iter().collect::<String>()));
+        body.push_str("</span></div>\n");
+    }
+
+    let title = format!(
+        "#{} {} – {}",
+        args.channel,
+        local_time(args.from, "%Y-%m-%d %H:%M"),
+        local_time(args.to, "%Y-%m-%d %H:%M"),
+    );
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>{1}</style>\n</head>\n<body>\n<h1>{0}</h1>\n{2}</body>\n</html>\n",
+        escape(&title),
+        STYLE,
+        body,
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use regex::Regex;
+use crate::chat::{ChatMessage, MessageKind};
+
+// Rules live in a plain text file, one per line:
+//
+//   # action   field  pattern
+//   hide       user   nightbot
+//   hide       badge  bot-badge
+//   dim        regex  ^!\w+
+//   collapse   type   usernotice
+//   hide       channel somechannel
+//
+// The first rule that matches a message decides what happens to it.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Hide,
+    Dim,
+    Collapse,
+}
+
+enum Matcher {
+    User(String),
+    Regex(Regex),
+    Badge(String),
+    Kind(MessageKind),
+    Channel(String),
+}
+
+impl Matcher {
+    fn matches(&self, m: &ChatMessage) -> bool {
+        match self {
+            Matcher::User(u) => m.sender.eq_ignore_ascii_case(u),
+            Matcher::Regex(re) => re.is_match(&m.message),
+            Matcher::Badge(b) => m.badges().iter().any(|(name, _)| name == b),
+            Matcher::Kind(k) => m.kind == *k,
+            Matcher::Channel(c) => m.channel.eq_ignore_ascii_case(c),
+        }
+    }
+}
+
+pub struct Rule {
+    source: String,
+    action: Action,
+    matcher: Matcher,
+    suppressed: u64,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Rule, String> {
+        // Columns are lined up with any amount of space, the pattern is the rest of the line
+        let line = line.trim();
+        let (action, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim_start();
+        let (field, pattern) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let pattern = pattern.trim();
+        let action = match action {
+            "hide" => Action::Hide,
+            "dim" => Action::Dim,
+            "collapse" => Action::Collapse,
+            other => return Err(format!("unknown action {:?}", other)),
+        };
+        if pattern.is_empty() {
+            return Err("missing pattern".to_string());
+        }
+
+        let matcher = match field {
+            "user" => Matcher::User(pattern.to_lowercase()),
+            "regex" => Matcher::Regex(Regex::new(pattern).map_err(|e| e.to_string())?),
+            "badge" => Matcher::Badge(pattern.to_string()),
+            "type" => Matcher::Kind(MessageKind::from_name(pattern)
+                .ok_or_else(|| format!("unknown message type {:?}", pattern))?),
+            "channel" => Matcher::Channel(pattern.trim_start_matches('#').to_string()),
+            _ => return Err(format!("unknown field {:?}", field)),
+        };
+
+        Ok(Rule {
+            source: line.to_string(),
+            action,
+            matcher,
+            suppressed: 0,
+        })
+    }
+}
+
+pub struct Filter {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    rules: Vec<Rule>,
+    // Switched off everything shows as it is, the rules stay loaded
+    enabled: bool,
+}
+
+impl Filter {
+    pub fn load(path: PathBuf) -> Self {
+        let mut filter = Self {
+            path,
+            modified: None,
+            rules: Vec::new(),
+            enabled: true,
+        };
+        filter.reload();
+        filter
+    }
+
+    /// Re-reads the rules file if it has been touched since we last loaded it.
+    pub fn reload_if_changed(&mut self) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return false;
+        }
+        self.reload();
+        true
+    }
+
+    pub fn reload(&mut self) {
+        self.modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(_) => {
+                self.rules.clear();
+                return;
+            }
+        };
+
+        let mut rules = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Rule::parse(line) {
+                Ok(mut rule) => {
+                    // Keep counters for rules that survived the reload untouched
+                    if let Some(old) = self.rules.iter().find(|r| r.source == rule.source) {
+                        rule.suppressed = old.suppressed;
+                    }
+                    rules.push(rule);
+                },
+                Err(e) => println!("{}:{}: {}", self.path.display(), n + 1, e),
+            }
+        }
+        println!("Loaded {} filter rules from {}", rules.len(), self.path.display());
+        self.rules = rules;
+    }
+
+    /// Returns what should happen to the message, or None if it should be shown untouched.
+    pub fn apply(&mut self, m: &ChatMessage) -> Option<Action> {
+        if !self.enabled {
+            return None;
+        }
+        let rule = self.rules.iter_mut().find(|r| r.matcher.matches(m))?;
+        rule.suppressed += 1;
+        Some(rule.action)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Each rule with the number of messages it has suppressed so far.
+    pub fn stats(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.rules.iter().map(|r| (r.source.as_str(), r.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_documented_examples() {
+        let examples = [
+            "hide       user   nightbot",
+            "hide       badge  bot-badge",
+            r"dim        regex  ^!\w+",
+            "collapse   type   usernotice",
+            "hide       channel somechannel",
+        ];
+        for line in examples {
+            assert!(Rule::parse(line).is_ok(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn pattern_is_rest_of_line() {
+        let rule = Rule::parse("dim   regex   hello  there ").unwrap();
+        assert_eq!(rule.action, Action::Dim);
+        match rule.matcher {
+            Matcher::Regex(re) => assert_eq!(re.as_str(), "hello  there"),
+            _ => panic!("expected a regex"),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(Rule::parse("hide user").is_err());
+        assert!(Rule::parse("shout user nightbot").is_err());
+        assert!(Rule::parse("hide   nobody  nightbot").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::input::InputLine;
+use crate::renderer::Screen;
+use crate::theme::Theme;
+use crate::view::{self, ChatView, ERROR, ERROR_BG};
+
+/// The `/` prompt, searching the chat as the pattern is typed. Searching goes from the newest
+/// message towards older ones, the way chat is read back.
+pub struct FindPrompt {
+    pub input: InputLine,
+    // Plain text or a regex, switched with Ctrl+R
+    regex: bool,
+    // Where the cursor was when the search started, every keystroke searches again from here
+    origin: Option<usize>,
+    error: Option<String>,
+}
+
+impl FindPrompt {
+    pub fn new(view: &ChatView) -> Self {
+        Self {
+            input: InputLine::new(),
+            regex: false,
+            origin: view.cursor_position(),
+            error: None,
+        }
+    }
+
+    /// Highlights what the pattern matches now and moves the cursor to the nearest match.
+    pub fn update(&mut self, view: &mut ChatView) {
+        view.set_cursor_position(self.origin);
+        self.error = None;
+        if self.input.text().is_empty() {
+            view.set_search(None);
+            return;
+        }
+        match view::search_regex(self.input.text(), self.regex) {
+            Ok(re) => {
+                view.set_search(Some(re));
+                view.next_match(false);
+            },
+            Err(e) => {
+                view.set_search(None);
+                // The regex crate's errors span several lines pointing at the problem
+                self.error = e.to_string().lines().last().map(|l| l.trim_start_matches("error: ").to_string());
+            },
+        }
+    }
+
+    pub fn toggle_regex(&mut self, view: &mut ChatView) {
+        self.regex = !self.regex;
+        self.update(view);
+    }
+
+    /// Gives up on the search, putting the cursor back where it was.
+    pub fn cancel(&self, view: &mut ChatView) {
+        view.set_search(None);
+        view.set_cursor_position(self.origin);
+    }
+
+    pub fn draw(&self, screen: &mut Screen, view: &ChatView, theme: &Theme) {
+        let prompt = if self.regex { "regex /" } else { "/" };
+        self.input.draw(screen, prompt, theme);
+        let info = match &self.error {
+            Some(e) => Some((e.clone(), ERROR, ERROR_BG)),
+            None if self.input.text().is_empty() => None,
+            None => match view.search_count() {
+                0 => Some(("no matches".to_string(), ERROR, theme.panel())),
+                1 => Some(("1 match".to_string(), theme.meta(), theme.panel())),
+                n => Some((format!("{} matches", n), theme.meta(), theme.panel())),
+            },
+        };
+        // Above the prompt, out of the way of what is being typed
+        if let Some((text, fg, bg)) = info {
+            let row = screen.rows().saturating_sub(self.input.rows(screen, prompt) + 1);
+            let len = text.chars().count() as u32 + 2;
+            let col = screen.cols().saturating_sub(len);
+            screen.fill(row, col, len, bg);
+            screen.print_colored(row, col + 1, &text, fg, bg);
+        }
+    }
+}
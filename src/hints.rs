@@ -0,0 +1,212 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use regex::Regex;
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+// Hint rules live in a plain text file, one per line:
+//
+//   # action  pattern
+//   open      (https?://|www\.)[^\s<>"]+
+//   copy      \b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b
+//   mpv       https://clips\.twitch\.tv/\S+
+//
+// `open` hands the match to xdg-open, `copy` puts it on the clipboard and anything else is run as
+// a program with the match as its only argument, in hint mode. Every match is underlined in the
+// chat, opens with xdg-open when clicked and gets a label in hint mode.
+
+const DEFAULT_RULES: &str = r#"open (https?://|www\.)[^\s<>"]+"#;
+
+// Labels are built from keys under the fingers first, like alacritty
+const ALPHABET: &str = "jfkdls;ahgurieowpq";
+
+const LABEL_FG: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const LABEL_BG: [f32; 3] = [1.0, 0.85, 0.2];
+const TYPED_BG: [f32; 3] = [0.6, 0.5, 0.1];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HintAction {
+    Open,
+    Copy,
+    Command(String),
+}
+
+impl HintAction {
+    /// Runs the action on a matched piece of text, reporting anything that went wrong.
+    pub fn run(&self, text: &str) -> Result<(), String> {
+        match self {
+            HintAction::Open => spawn("xdg-open", text),
+            HintAction::Command(program) => spawn(program, text),
+            HintAction::Copy => copy(text),
+        }
+    }
+}
+
+fn spawn(program: &str, arg: &str) -> Result<(), String> {
+    Command::new(program)
+        .arg(arg)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(reap)
+        .map_err(|e| format!("{}: {}", program, e))
+}
+
+// Waits for a child off the UI thread so it doesn't linger as a zombie
+fn reap(mut child: Child) {
+    std::thread::spawn(move || child.wait());
+}
+
+fn copy(text: &str) -> Result<(), String> {
+    let (program, args): (&str, &[&str]) = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        ("wl-copy", &[])
+    } else {
+        ("xclip", &["-selection", "clipboard"])
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).map_err(|e| format!("{}: {}", program, e))?;
+    }
+    reap(child);
+    Ok(())
+}
+
+pub struct HintRule {
+    pub action: HintAction,
+    pub regex: Regex,
+}
+
+fn parse_rules(contents: &str, source: &str) -> Vec<HintRule> {
+    let mut rules = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (action, pattern) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let action = match action {
+            "open" => HintAction::Open,
+            "copy" => HintAction::Copy,
+            program => HintAction::Command(program.to_string()),
+        };
+        match Regex::new(pattern.trim()) {
+            Ok(regex) if !pattern.trim().is_empty() => rules.push(HintRule { action, regex }),
+            Ok(_) => println!("{}:{}: missing pattern", source, n + 1),
+            Err(e) => println!("{}:{}: {}", source, n + 1, e),
+        }
+    }
+    rules
+}
+
+/// Reads the hint rules, falling back to opening URLs when there is no rules file.
+pub fn load(path: &Path) -> Vec<HintRule> {
+    match fs::read_to_string(path) {
+        Ok(contents) => parse_rules(&contents, &path.display().to_string()),
+        Err(_) => parse_rules(DEFAULT_RULES, "default hints"),
+    }
+}
+
+/// A match on screen, possibly wrapped over several rows.
+#[derive(Clone, Debug)]
+pub struct Link {
+    pub text: String,
+    // Index of the rule that matched
+    pub rule: usize,
+    // (row, col, length in cells) of each piece
+    pub segments: Vec<(u32, u32, usize)>,
+}
+
+impl Link {
+    pub fn contains(&self, col: u32, row: u32) -> bool {
+        self.segments.iter().any(|(r, c, len)| *r == row && col >= *c && col < c + *len as u32)
+    }
+}
+
+/// Labels `count` things, all labels the same length so none is a prefix of another.
+fn labels(count: usize) -> Vec<String> {
+    let alphabet: Vec<char> = ALPHABET.chars().collect();
+    let mut length = 1;
+    while alphabet.len().pow(length) < count {
+        length += 1;
+    }
+    (0..count).map(|mut i| {
+        let mut label = String::new();
+        for _ in 0..length {
+            label.insert(0, alphabet[i % alphabet.len()]);
+            i /= alphabet.len();
+        }
+        label
+    }).collect()
+}
+
+/// Alacritty style hint mode: every visible link gets a label and typing one runs its action.
+pub struct HintMode {
+    links: Vec<(String, Link)>,
+    typed: String,
+    // Typing a label in capitals copies the link instead of running its action
+    copy: bool,
+}
+
+pub enum HintResult {
+    Pending,
+    Cancelled,
+    Chosen(Link, bool),
+}
+
+impl HintMode {
+    pub fn new(links: Vec<Link>) -> Self {
+        Self {
+            links: labels(links.len()).into_iter().zip(links).collect(),
+            typed: String::new(),
+            copy: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    pub fn insert(&mut self, c: char) -> HintResult {
+        if c.is_control() {
+            return HintResult::Pending;
+        }
+        self.copy |= c.is_uppercase();
+        self.typed.extend(c.to_lowercase());
+        if let Some((_, link)) = self.links.iter().find(|(label, _)| *label == self.typed) {
+            return HintResult::Chosen(link.clone(), self.copy);
+        }
+        if self.links.iter().any(|(label, _)| label.starts_with(&self.typed)) {
+            HintResult::Pending
+        } else {
+            HintResult::Cancelled
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.typed.pop();
+    }
+
+    /// Draws the labels over the chat, which must already be drawn.
+    pub fn draw(&self, screen: &mut Screen, theme: &Theme) {
+        for (label, link) in &self.links {
+            if !label.starts_with(&self.typed) {
+                continue;
+            }
+            if let Some((row, col, _)) = link.segments.first() {
+                let typed = self.typed.chars().count();
+                let (done, rest): (String, String) = (label.chars().take(typed).collect(), label.chars().skip(typed).collect());
+                screen.print_colored(*row, *col, &done, LABEL_FG, TYPED_BG);
+                screen.print_colored(*row, *col + typed as u32, &rest, LABEL_FG, LABEL_BG);
+            }
+        }
+        let row = screen.rows().saturating_sub(1);
+        screen.fill_row(row, theme.panel());
+        screen.print_colored(row, 1, "hint: type a label to open it, in capitals to copy it, Esc to cancel", theme.text(), theme.panel());
+    }
+}
@@ -0,0 +1,277 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use rusqlite::{params, params_from_iter, Connection};
+use crate::chat::{self, ChatMessage, MessageKind};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        msg_id TEXT UNIQUE,
+        channel TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        ts INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        text TEXT NOT NULL,
+        tags TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_channel_ts ON messages (channel, ts);
+    CREATE INDEX IF NOT EXISTS messages_sender_ts ON messages (sender, ts);
+    CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5 (
+        text, sender, content='messages', content_rowid='id'
+    );
+    CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts (rowid, text, sender) VALUES (new.id, new.text, new.sender);
+    END;
+    CREATE TABLE IF NOT EXISTS notes (
+        user TEXT PRIMARY KEY,
+        note TEXT NOT NULL,
+        updated INTEGER NOT NULL
+    );
+";
+
+// Markers handed to fts5's highlight() to find the matched ranges again
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+pub fn default_path() -> PathBuf {
+    crate::config::data_dir().join("history.db")
+}
+
+struct Record {
+    msg_id: Option<String>,
+    channel: String,
+    sender: String,
+    timestamp: i64,
+    kind: &'static str,
+    text: String,
+    tags: String,
+}
+
+/// Hands messages to a background thread that owns the write connection, so a slow disk never
+/// holds up the event loop.
+pub struct HistoryWriter {
+    tx: Sender<Record>,
+}
+
+impl HistoryWriter {
+    pub fn spawn(path: PathBuf) -> rusqlite::Result<HistoryWriter> {
+        let conn = History::open(&path)?.conn;
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("history".into())
+            .spawn(move || write_loop(conn, rx))
+            .expect("failed to spawn history thread");
+        Ok(Self { tx })
+    }
+
+    pub fn record(&self, m: &ChatMessage) {
+        let record = Record {
+            msg_id: Some(m.id.clone()).filter(|id| !id.is_empty()),
+            channel: m.channel.clone(),
+            sender: m.sender.clone(),
+            timestamp: m.timestamp,
+            kind: m.kind.name(),
+            text: m.message.clone(),
+            tags: m.tags_string(),
+        };
+        if self.tx.send(record).is_err() {
+            println!("History writer has gone away");
+        }
+    }
+}
+
+fn write_loop(mut conn: Connection, rx: Receiver<Record>) {
+    while let Ok(first) = rx.recv() {
+        // Batch up whatever else has arrived into one transaction
+        let mut batch = vec![first];
+        batch.extend(rx.try_iter());
+
+        let result = conn.transaction().and_then(|tx| {
+            {
+                let mut insert = tx.prepare_cached(
+                    "INSERT OR IGNORE INTO messages (msg_id, channel, sender, ts, kind, text, tags)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )?;
+                for r in &batch {
+                    insert.execute(params![r.msg_id, r.channel, r.sender, r.timestamp, r.kind, r.text, r.tags])?;
+                }
+            }
+            tx.commit()
+        });
+        if let Err(e) = result {
+            println!("Failed to write {} messages to history: {}", batch.len(), e);
+        }
+    }
+}
+
+pub struct Hit {
+    pub channel: String,
+    pub sender: String,
+    pub timestamp: i64,
+    pub text: String,
+    /// Char ranges within `text` that matched the query.
+    pub matches: Vec<Range<usize>>,
+}
+
+/// Read side of the message history.
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: &Path) -> rusqlite::Result<History> {
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                println!("Failed to create {}: {}", dir.display(), e);
+            }
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Full text search over everything we've seen, newest first. Besides plain words the query
+    /// understands `from:nick` and `in:#channel` to narrow things down.
+    pub fn search(&self, query: &str, limit: usize) -> rusqlite::Result<Vec<Hit>> {
+        let mut terms = Vec::new();
+        let mut clauses = Vec::new();
+        let mut args = Vec::new();
+        for word in query.split_whitespace() {
+            if let Some(nick) = word.strip_prefix("from:") {
+                clauses.push("m.sender = ?");
+                args.push(nick.trim_start_matches('@').to_lowercase());
+            } else if let Some(channel) = word.strip_prefix("in:") {
+                clauses.push("m.channel = ?");
+                args.push(channel.trim_start_matches('#').to_lowercase());
+            } else {
+                // Quote every term so punctuation is never read as fts syntax
+                terms.push(format!("\"{}\"", word.replace('"', "\"\"")));
+            }
+        }
+
+        let sql = if terms.is_empty() {
+            format!(
+                "SELECT m.channel, m.sender, m.ts, m.text FROM messages m
+                 WHERE {} ORDER BY m.ts DESC LIMIT {}",
+                if clauses.is_empty() { "1".to_string() } else { clauses.join(" AND ") },
+                limit,
+            )
+        } else {
+            args.insert(0, terms.join(" "));
+            clauses.insert(0, "messages_fts MATCH ?");
+            format!(
+                "SELECT m.channel, m.sender, m.ts, highlight(messages_fts, 0, '{}', '{}')
+                 FROM messages_fts JOIN messages m ON m.id = messages_fts.rowid
+                 WHERE {} ORDER BY m.ts DESC LIMIT {}",
+                MATCH_START, MATCH_END, clauses.join(" AND "), limit,
+            )
+        };
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args.iter()), |row| {
+            let (text, matches) = split_highlights(&row.get::<_, String>(3)?);
+            Ok(Hit {
+                channel: row.get(0)?,
+                sender: row.get(1)?,
+                timestamp: row.get(2)?,
+                text,
+                matches,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Everything said in a channel between two millisecond timestamps, oldest first.
+    pub fn range(&self, channel: &str, from: i64, to: i64) -> rusqlite::Result<Vec<ChatMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT msg_id, channel, sender, ts, kind, text, tags FROM messages
+             WHERE channel = ?1 AND ts >= ?2 AND ts < ?3 ORDER BY ts",
+        )?;
+        let rows = stmt.query_map(params![channel, from, to], message_from_row)?;
+        rows.collect()
+    }
+
+    /// The newest `limit` messages from a user, oldest first.
+    pub fn user_messages(&self, sender: &str, limit: usize) -> rusqlite::Result<Vec<ChatMessage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT msg_id, channel, sender, ts, kind, text, tags FROM messages
+             WHERE sender = ?1 ORDER BY ts DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![sender, limit as i64], message_from_row)?;
+        let mut messages = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// When we first saw a user and how many messages we have from them.
+    pub fn user_stats(&self, sender: &str) -> rusqlite::Result<(Option<i64>, i64)> {
+        self.conn.query_row(
+            "SELECT MIN(ts), COUNT(*) FROM messages WHERE sender = ?1",
+            params![sender],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    /// Everyone who has spoken since `since`, as (channel, sender, when they last spoke).
+    pub fn last_seen_since(&self, since: i64) -> rusqlite::Result<Vec<(String, String, i64)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT channel, sender, MAX(ts) FROM messages WHERE ts >= ?1 GROUP BY channel, sender",
+        )?;
+        let rows = stmt.query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect()
+    }
+
+    pub fn note(&self, user: &str) -> rusqlite::Result<Option<String>> {
+        let mut stmt = self.conn.prepare("SELECT note FROM notes WHERE user = ?1")?;
+        let mut rows = stmt.query_map(params![user], |row| row.get(0))?;
+        rows.next().transpose()
+    }
+
+    /// Saves the note kept about a user, an empty note removes it.
+    pub fn set_note(&self, user: &str, note: &str) -> rusqlite::Result<()> {
+        if note.is_empty() {
+            self.conn.execute("DELETE FROM notes WHERE user = ?1", params![user])?;
+        } else {
+            self.conn.execute(
+                "INSERT INTO notes (user, note, updated) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (user) DO UPDATE SET note = excluded.note, updated = excluded.updated",
+                params![user, note, chat::now_millis()],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn message_from_row(row: &rusqlite::Row) -> rusqlite::Result<ChatMessage> {
+    Ok(ChatMessage {
+        id: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+        channel: row.get(1)?,
+        sender: row.get(2)?,
+        timestamp: row.get(3)?,
+        kind: MessageKind::from_name(&row.get::<_, String>(4)?).unwrap_or(MessageKind::Chat),
+        message: row.get(5)?,
+        tags: chat::parse_tags(&row.get::<_, String>(6)?),
+    })
+}
+
+fn split_highlights(marked: &str) -> (String, Vec<Range<usize>>) {
+    let mut text = String::with_capacity(marked.len());
+    let mut matches = Vec::new();
+    let mut start = 0;
+    let mut len = 0;
+    for c in marked.chars() {
+        match c {
+            MATCH_START => start = len,
+            MATCH_END => matches.push(start..len),
+            c => {
+                text.push(c);
+                len += 1;
+            },
+        }
+    }
+    (text, matches)
+}
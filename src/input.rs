@@ -0,0 +1,290 @@
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use winit::event::{ModifiersState, VirtualKeyCode};
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+/// Byte offsets where each extended grapheme cluster of `text` starts, plus the end of the text.
+fn grapheme_boundaries(text: &str) -> Vec<usize> {
+    text.grapheme_indices(true).map(|(i, _)| i).chain(std::iter::once(text.len())).collect()
+}
+
+/// A single line of text being typed, e.g. a search prompt or a chat message, with readline style
+/// editing.
+pub struct InputLine {
+    text: String,
+    // Byte offset into `text`, always on a grapheme boundary
+    cursor: usize,
+}
+
+impl InputLine {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn with_text(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            cursor: text.len(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Moves the cursor to a byte offset, which must be on a char boundary.
+    pub fn set_cursor(&mut self, pos: usize) {
+        self.cursor = pos.min(self.text.len());
+    }
+
+    /// Replaces the whole line, leaving the cursor at the end.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    /// The whitespace separated word the cursor is at the end of, with the byte offset it starts
+    /// at.
+    pub fn word_before_cursor(&self) -> (usize, &str) {
+        let before = &self.text[..self.cursor];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8());
+        (start, &before[start..])
+    }
+
+    /// Replaces the text between `start` and the cursor, leaving the cursor after the replacement.
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        self.text.replace_range(start..self.cursor, text);
+        self.cursor = start + text.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Feeds a character from `ReceivedCharacter`, ignoring control characters which arrive as
+    /// their own key events.
+    pub fn insert(&mut self, c: char) {
+        if !c.is_control() {
+            self.text.insert(self.cursor, c);
+            self.cursor += c.len_utf8();
+            // A combining mark typed after the cursor joins the grapheme, keep the cursor after it
+            self.cursor = self.next_boundary(self.cursor - c.len_utf8()).max(self.cursor);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        let start = self.prev_boundary(self.cursor);
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Handles the editing keys, returning false for keys that mean something else.
+    pub fn edit(&mut self, key: VirtualKeyCode, modifiers: ModifiersState) -> bool {
+        let ctrl = modifiers.ctrl();
+        match key {
+            VirtualKeyCode::Back if ctrl => self.delete_to(self.word_start()),
+            VirtualKeyCode::Back => self.backspace(),
+            VirtualKeyCode::Delete if ctrl => self.delete_to(self.word_end()),
+            VirtualKeyCode::Delete => self.delete_to(self.next_boundary(self.cursor)),
+            VirtualKeyCode::Left if ctrl => self.cursor = self.word_start(),
+            VirtualKeyCode::Left => self.cursor = self.prev_boundary(self.cursor),
+            VirtualKeyCode::Right if ctrl => self.cursor = self.word_end(),
+            VirtualKeyCode::Right => self.cursor = self.next_boundary(self.cursor),
+            VirtualKeyCode::Home => self.cursor = 0,
+            VirtualKeyCode::A if ctrl => self.cursor = 0,
+            VirtualKeyCode::End => self.cursor = self.text.len(),
+            VirtualKeyCode::E if ctrl => self.cursor = self.text.len(),
+            VirtualKeyCode::U if ctrl => self.delete_to(0),
+            VirtualKeyCode::K if ctrl => self.delete_to(self.text.len()),
+            VirtualKeyCode::W if ctrl => self.delete_to(self.word_start()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Deletes between the cursor and `to`, on whichever side of the cursor that is.
+    fn delete_to(&mut self, to: usize) {
+        let (start, end) = (self.cursor.min(to), self.cursor.max(to));
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    fn prev_boundary(&self, pos: usize) -> usize {
+        grapheme_boundaries(&self.text).into_iter().rev().find(|b| *b < pos).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, pos: usize) -> usize {
+        grapheme_boundaries(&self.text).into_iter().find(|b| *b > pos).unwrap_or(self.text.len())
+    }
+
+    /// Where the word before the cursor starts, skipping whitespace first like Ctrl-W in a shell.
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before.rfind(char::is_whitespace).map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8())
+    }
+
+    fn word_end(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let word = after.len() - after.trim_start().len();
+        let end = after[word..].find(char::is_whitespace).map_or(after.len(), |i| word + i);
+        self.cursor + end
+    }
+
+    /// How many rows the line takes at the bottom of the window once wrapped.
+    pub fn rows(&self, screen: &Screen, prompt: &str) -> u32 {
+        let width = (screen.cols().saturating_sub(2) as usize).max(1);
+        let chars = prompt.chars().count() + self.text.chars().count();
+        let cursor = prompt.chars().count() + self.text[..self.cursor].chars().count();
+        // The cursor can sit past the last char, which takes a row of its own at the wrap point
+        let lines = chars.max(cursor + 1).div_ceil(width);
+        let max_lines = (screen.rows() as usize / 2).max(1);
+        lines.min(max_lines) as u32
+    }
+
+    /// Where the wrapped line goes: (width of a line, first line shown, number shown, top row).
+    fn layout(&self, screen: &Screen, prompt: &str) -> (usize, usize, usize, u32) {
+        let width = (screen.cols().saturating_sub(2) as usize).max(1);
+        let cursor = prompt.chars().count() + self.text[..self.cursor].chars().count();
+        let shown = self.rows(screen, prompt) as usize;
+        // Keep the cursor's line in view when the text is taller than that
+        let first = (cursor / width + 1).saturating_sub(shown);
+        (width, first, shown, screen.rows().saturating_sub(shown as u32))
+    }
+
+    /// Draws the line across the bottom of the window, preceded by `prompt`. Long lines wrap
+    /// upwards over the chat, up to half the window.
+    pub fn draw(&self, screen: &mut Screen, prompt: &str, theme: &Theme) {
+        let chars: Vec<char> = prompt.chars().chain(self.text.chars()).collect();
+        let cursor = prompt.chars().count() + self.text[..self.cursor].chars().count();
+        let (width, first, shown, top) = self.layout(screen, prompt);
+
+        for n in 0..shown {
+            let row = top + n as u32;
+            screen.fill_row(row, theme.panel());
+            let line: String = chars.iter().skip((first + n) * width).take(width).collect();
+            screen.print_colored(row, 1, &line, theme.text(), theme.panel());
+        }
+
+        let [r, g, b, _] = theme.text();
+        let (cell_width, cell_height) = screen.cell_size();
+        let row = top + (cursor / width - first) as u32;
+        let col = 1 + (cursor % width) as u32;
+        screen.rect(col as f32 * cell_width, row as f32 * cell_height, (cell_width / 8.0).max(2.0), cell_height, [r, g, b]);
+    }
+
+    /// Squiggles under byte ranges of the text, which must already be drawn with the same prompt.
+    pub fn squiggle(&self, screen: &mut Screen, prompt: &str, ranges: &[Range<usize>], color: [f32; 3]) {
+        let (width, first, shown, top) = self.layout(screen, prompt);
+        let prompt_len = prompt.chars().count();
+        for range in ranges {
+            let mut pos = prompt_len + self.text[..range.start].chars().count();
+            let end = pos + self.text[range.clone()].chars().count();
+            // Split where the line wraps
+            while pos < end {
+                let len = (end - pos).min(width - pos % width);
+                let line = pos / width;
+                if line >= first && line < first + shown {
+                    screen.squiggle(top + (line - first) as u32, 1 + (pos % width) as u32, len, color);
+                }
+                pos += len;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "\u{1f469}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+    const THUMBS_UP: &str = "\u{1f44d}\u{1f3fd}";
+    const FLAGS: &str = "\u{1f1eb}\u{1f1f7}\u{1f1e9}\u{1f1ea}";
+
+    fn press(line: &mut InputLine, key: VirtualKeyCode) {
+        assert!(line.edit(key, ModifiersState::empty()));
+    }
+
+    fn ctrl(line: &mut InputLine, key: VirtualKeyCode) {
+        assert!(line.edit(key, ModifiersState::CTRL));
+    }
+
+    #[test]
+    fn boundaries_follow_extended_graphemes() {
+        assert_eq!(grapheme_boundaries("e\u{301}x"), [0, 3, 4]);
+        assert_eq!(grapheme_boundaries(FAMILY), [0, FAMILY.len()]);
+        assert_eq!(grapheme_boundaries(THUMBS_UP), [0, THUMBS_UP.len()]);
+        assert_eq!(grapheme_boundaries(FLAGS), [0, 8, 16]);
+        assert_eq!(grapheme_boundaries("a\r\nb"), [0, 1, 3, 4]);
+        // A Hangul syllable spelled out in jamo
+        assert_eq!(grapheme_boundaries("\u{1100}\u{1161}\u{11a8}"), [0, 9]);
+        assert_eq!(grapheme_boundaries(""), [0]);
+    }
+
+    #[test]
+    fn cursor_moves_over_whole_graphemes() {
+        let mut line = InputLine::with_text(&format!("e\u{301}{}{}{}", FAMILY, THUMBS_UP, FLAGS));
+        let mut stops = vec![line.cursor()];
+        while line.cursor() > 0 {
+            press(&mut line, VirtualKeyCode::Left);
+            stops.push(line.cursor());
+        }
+        let flags = 3 + FAMILY.len() + THUMBS_UP.len();
+        assert_eq!(stops, [flags + 16, flags + 8, flags, 3 + FAMILY.len(), 3, 0]);
+
+        press(&mut line, VirtualKeyCode::Right);
+        assert_eq!(line.cursor(), 3);
+        press(&mut line, VirtualKeyCode::Delete);
+        assert_eq!(line.text(), format!("e\u{301}{}{}", THUMBS_UP, FLAGS));
+        press(&mut line, VirtualKeyCode::End);
+        press(&mut line, VirtualKeyCode::Back);
+        assert_eq!(line.text(), format!("e\u{301}{}\u{1f1eb}\u{1f1f7}", THUMBS_UP));
+    }
+
+    #[test]
+    fn combining_mark_joins_the_grapheme_before_the_cursor() {
+        let mut line = InputLine::new();
+        line.insert('e');
+        line.insert('\u{301}');
+        assert_eq!(line.cursor(), 3);
+        line.backspace();
+        assert_eq!(line.text(), "");
+
+        // Typed in the middle, the mark joins the letter before it and the cursor stays after both
+        let mut line = InputLine::with_text("ab");
+        line.set_cursor(1);
+        line.insert('\u{308}');
+        assert_eq!(line.text(), "a\u{308}b");
+        assert_eq!(line.cursor(), 3);
+    }
+
+    #[test]
+    fn word_deletes_keep_graphemes_whole() {
+        let mut line = InputLine::with_text(&format!("caf\u{65}\u{301} {}  {}", FAMILY, FLAGS));
+        ctrl(&mut line, VirtualKeyCode::W);
+        assert_eq!(line.text(), format!("caf\u{65}\u{301} {}  ", FAMILY));
+        ctrl(&mut line, VirtualKeyCode::W);
+        assert_eq!(line.text(), "caf\u{65}\u{301} ");
+
+        ctrl(&mut line, VirtualKeyCode::Left);
+        assert_eq!(line.cursor(), 0);
+        ctrl(&mut line, VirtualKeyCode::Right);
+        assert_eq!(line.cursor(), 6);
+
+        line.set_text(&format!("{} and {}", THUMBS_UP, FLAGS));
+        line.set_cursor(THUMBS_UP.len() + 4);
+        ctrl(&mut line, VirtualKeyCode::U);
+        assert_eq!(line.text(), format!(" {}", FLAGS));
+        assert_eq!(line.cursor(), 0);
+        ctrl(&mut line, VirtualKeyCode::K);
+        assert_eq!(line.text(), "");
+    }
+}
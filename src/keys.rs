@@ -0,0 +1,654 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use winit::event::{ModifiersState, VirtualKeyCode};
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+// Vim style modal keybindings. Every binding can be changed in a plain text file, one per line:
+//
+//   # modes         keys    action
+//   normal          <C-n>   down
+//   normal,visual   J       half_page_down
+//   insert          <C-j>   send
+//   normal          ZZ      nop
+//
+// Keys are written like vim's: plain characters, or named keys and modifiers in angle brackets
+// such as <Esc>, <CR>, <Tab>, <Space>, <F2>, <C-d>, <A-x> and <S-Tab>. `nop` takes a key away
+// from its default. In normal and visual mode a count can come first, e.g. 5j.
+
+// How long to wait for the rest of a sequence, e.g. after the first g of gg
+const SEQUENCE_TIMEOUT: Duration = Duration::from_millis(1000);
+const MAX_COUNT: u32 = 9999;
+
+const DEFAULT_BINDINGS: &str = "
+normal,visual   j           down
+normal,visual   <Down>      down
+normal,visual   k           up
+normal,visual   <Up>        up
+normal,visual   <C-d>       half_page_down
+normal,visual   <C-u>       half_page_up
+normal,visual   <PageDown>  scroll_page_down
+normal,visual   <PageUp>    scroll_page_up
+normal,visual   <C-e>       scroll_down
+normal,visual   <C-y>       scroll_up
+normal,visual   <End>       scroll_bottom
+normal,visual   gg          top
+normal,visual   G           bottom
+normal,visual   ]m          next_mention
+normal,visual   [m          previous_mention
+normal,visual   :           command_line
+normal,visual   /           search
+normal,visual   n           next_match
+normal,visual   N           previous_match
+normal          <Esc>       escape
+normal          i           insert
+normal          a           insert
+normal          <CR>        insert
+normal          r           reply
+normal          u           user_card
+normal          yy          copy
+normal          v           visual
+normal          V           visual
+normal          <Space>     mark
+normal,visual   t           timeout
+normal,visual   b           ban
+normal          <C-f>       history_search
+normal          <C-r>       select_regex
+normal          <C-o>       hints
+normal          <C-m>       mute
+normal          <C-Tab>     next_channel
+normal          <F2>        chatters
+normal          <F3>        stats
+normal          <F5>        reload_filters
+normal          ZZ          quit
+visual          y           copy
+visual          <Esc>       normal
+visual          v           normal
+visual          V           normal
+insert          <Esc>       normal
+insert          <CR>        send
+insert          <Tab>       complete
+insert          <S-Tab>     complete_previous
+insert          <Up>        history_previous
+insert          <Down>      history_next
+insert          <F7>        spell_suggest
+insert          <C-Tab>     next_channel
+command         <Esc>       normal
+command         <CR>        execute
+command         <Tab>       complete
+command         <S-Tab>     complete_previous
+command         <Up>        history_previous
+command         <Down>      history_next
+";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Moving around the chat, nothing open.
+    Normal,
+    /// Typing a message.
+    Insert,
+    /// Selecting a range of messages.
+    Visual,
+    /// Typing a `:` command.
+    Command,
+}
+
+impl Mode {
+    fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "normal" => Some(Mode::Normal),
+            "insert" => Some(Mode::Insert),
+            "visual" => Some(Mode::Visual),
+            "command" => Some(Mode::Command),
+            _ => None,
+        }
+    }
+}
+
+/// Everything a key can be bound to, by the name used in the bindings file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Nop,
+    Down,
+    Up,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    ScrollDown,
+    ScrollUp,
+    ScrollPageDown,
+    ScrollPageUp,
+    ScrollBottom,
+    NextMention,
+    PreviousMention,
+    Search,
+    NextMatch,
+    PreviousMatch,
+    UserCard,
+    Reply,
+    Copy,
+    Mark,
+    Timeout,
+    Ban,
+    Insert,
+    Visual,
+    CommandLine,
+    Normal,
+    Escape,
+    Quit,
+    HistorySearch,
+    SelectRegex,
+    Hints,
+    Mute,
+    NextChannel,
+    Chatters,
+    Stats,
+    ReloadFilters,
+    Send,
+    Complete,
+    CompletePrevious,
+    HistoryPrevious,
+    HistoryNext,
+    SpellSuggest,
+    Execute,
+}
+
+const ACTIONS: &[(&str, Action)] = &[
+    ("nop", Action::Nop),
+    ("down", Action::Down),
+    ("up", Action::Up),
+    ("half_page_down", Action::HalfPageDown),
+    ("half_page_up", Action::HalfPageUp),
+    ("page_down", Action::PageDown),
+    ("page_up", Action::PageUp),
+    ("top", Action::Top),
+    ("bottom", Action::Bottom),
+    ("scroll_down", Action::ScrollDown),
+    ("scroll_up", Action::ScrollUp),
+    ("scroll_page_down", Action::ScrollPageDown),
+    ("scroll_page_up", Action::ScrollPageUp),
+    ("scroll_bottom", Action::ScrollBottom),
+    ("next_mention", Action::NextMention),
+    ("previous_mention", Action::PreviousMention),
+    ("search", Action::Search),
+    ("next_match", Action::NextMatch),
+    ("previous_match", Action::PreviousMatch),
+    ("user_card", Action::UserCard),
+    ("reply", Action::Reply),
+    ("copy", Action::Copy),
+    ("mark", Action::Mark),
+    ("timeout", Action::Timeout),
+    ("ban", Action::Ban),
+    ("insert", Action::Insert),
+    ("visual", Action::Visual),
+    ("command_line", Action::CommandLine),
+    ("normal", Action::Normal),
+    ("escape", Action::Escape),
+    ("quit", Action::Quit),
+    ("history_search", Action::HistorySearch),
+    ("select_regex", Action::SelectRegex),
+    ("hints", Action::Hints),
+    ("mute", Action::Mute),
+    ("next_channel", Action::NextChannel),
+    ("chatters", Action::Chatters),
+    ("stats", Action::Stats),
+    ("reload_filters", Action::ReloadFilters),
+    ("send", Action::Send),
+    ("complete", Action::Complete),
+    ("complete_previous", Action::CompletePrevious),
+    ("history_previous", Action::HistoryPrevious),
+    ("history_next", Action::HistoryNext),
+    ("spell_suggest", Action::SpellSuggest),
+    ("execute", Action::Execute),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|(n, _)| *n == name).map(|(_, a)| *a)
+    }
+
+    /// Every action name, for completing them on the command line.
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        ACTIONS.iter().map(|(n, _)| *n)
+    }
+
+    /// Actions that move the cursor, which scrolls the view to keep it in sight.
+    pub fn moves_cursor(&self) -> bool {
+        matches!(self,
+            Action::Down | Action::Up | Action::HalfPageDown | Action::HalfPageUp | Action::PageDown | Action::PageUp
+            | Action::Top | Action::Bottom | Action::NextMention | Action::PreviousMention
+            | Action::NextMatch | Action::PreviousMatch)
+    }
+
+    /// Actions that still work while a panel like the user card is open.
+    fn is_global(&self) -> bool {
+        matches!(self, Action::HistorySearch | Action::NextChannel | Action::ReloadFilters)
+    }
+}
+
+/// Names a key press that doesn't type a character: named keys, and anything with Ctrl or Alt
+/// held. Plain characters come from `from_char` so they follow the keyboard layout.
+pub fn from_keycode(code: VirtualKeyCode, modifiers: ModifiersState) -> Option<String> {
+    let name = match code {
+        VirtualKeyCode::Escape => "Esc".to_string(),
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => "CR".to_string(),
+        VirtualKeyCode::Tab => "Tab".to_string(),
+        VirtualKeyCode::Back => "BS".to_string(),
+        VirtualKeyCode::Delete => "Del".to_string(),
+        VirtualKeyCode::Insert => "Insert".to_string(),
+        VirtualKeyCode::Up => "Up".to_string(),
+        VirtualKeyCode::Down => "Down".to_string(),
+        VirtualKeyCode::Left => "Left".to_string(),
+        VirtualKeyCode::Right => "Right".to_string(),
+        VirtualKeyCode::Home => "Home".to_string(),
+        VirtualKeyCode::End => "End".to_string(),
+        VirtualKeyCode::PageUp => "PageUp".to_string(),
+        VirtualKeyCode::PageDown => "PageDown".to_string(),
+        code => {
+            // Letters are A to Z, digits Key0 to Key9 and function keys F1 to F24
+            let debug = format!("{:?}", code);
+            let is_function = debug.len() > 1 && debug.starts_with('F') && debug[1..].chars().all(|c| c.is_ascii_digit());
+            if is_function {
+                debug
+            } else if !(modifiers.ctrl() || modifiers.alt()) {
+                return None;
+            } else if debug.len() == 1 {
+                debug.to_lowercase()
+            } else {
+                debug.strip_prefix("Key").filter(|d| d.len() == 1)?.to_string()
+            }
+        },
+    };
+    // Shift is already in the character for letters, so only named keys carry it
+    let shift = modifiers.shift() && name.chars().count() > 1;
+    Some(key_name(&name, modifiers.ctrl(), modifiers.alt(), shift))
+}
+
+/// Names a typed character, None for control characters and anything typed with Ctrl or Alt,
+/// which `from_keycode` names instead.
+pub fn from_char(c: char, modifiers: ModifiersState) -> Option<String> {
+    if c.is_control() || modifiers.ctrl() || modifiers.alt() || modifiers.logo() {
+        return None;
+    }
+    Some(match c {
+        ' ' => "<Space>".to_string(),
+        '<' => "<lt>".to_string(),
+        c => c.to_string(),
+    })
+}
+
+fn key_name(name: &str, ctrl: bool, alt: bool, shift: bool) -> String {
+    if !(ctrl || alt || shift) && name.chars().count() == 1 {
+        return name.to_string();
+    }
+    let mut key = String::from("<");
+    if ctrl {
+        key.push_str("C-");
+    }
+    if alt {
+        key.push_str("A-");
+    }
+    if shift {
+        key.push_str("S-");
+    }
+    key.push_str(name);
+    key.push('>');
+    key
+}
+
+/// Canonical name for what's between the angle brackets in a bindings file, e.g. `c-D` is `<C-d>`.
+fn parse_bracketed(inner: &str) -> Option<String> {
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    let mut rest = inner;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+        match rest.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => ctrl = true,
+            b'A' | b'M' => alt = true,
+            b'S' => shift = true,
+            _ => return None,
+        }
+        rest = &rest[2..];
+    }
+    let lower = rest.to_lowercase();
+    let name = match lower.as_str() {
+        "esc" | "escape" => "Esc",
+        "cr" | "enter" | "return" => "CR",
+        "tab" => "Tab",
+        "bs" | "backspace" => "BS",
+        "del" | "delete" => "Del",
+        "insert" => "Insert",
+        "space" => "Space",
+        "lt" => return Some("<lt>".to_string()),
+        "up" => "Up",
+        "down" => "Down",
+        "left" => "Left",
+        "right" => "Right",
+        "home" => "Home",
+        "end" => "End",
+        "pageup" => "PageUp",
+        "pagedown" => "PageDown",
+        f if f.starts_with('f') && f.len() > 1 && f[1..].chars().all(|c| c.is_ascii_digit()) => {
+            return Some(key_name(&f.to_uppercase(), ctrl, alt, shift));
+        },
+        _ if rest.chars().count() == 1 => {
+            let c = if ctrl || alt { lower } else { rest.to_string() };
+            return Some(key_name(&c, ctrl, alt, false));
+        },
+        _ => return None,
+    };
+    Some(key_name(name, ctrl, alt, shift))
+}
+
+/// Splits a written key sequence like `gg` or `<C-w>j` into its keys.
+fn parse_keys(s: &str) -> Result<Vec<String>, String> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>').filter(|end| *end > 1) {
+                let inner = &rest[1..end];
+                keys.push(parse_bracketed(inner).ok_or_else(|| format!("unknown key <{}>", inner))?);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        keys.push(if c == '<' { "<lt>".to_string() } else { c.to_string() });
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return Err("missing keys".to_string());
+    }
+    Ok(keys)
+}
+
+/// What a key press came to.
+pub enum KeyResult {
+    /// Run an action, with the count typed before it or 1.
+    Action(Action, u32),
+    /// The keys so far start a longer binding, wait for more.
+    Pending,
+    /// Nothing is bound, the key is for whatever is underneath, e.g. typing.
+    Unbound,
+}
+
+/// Turns key presses into actions for the current mode.
+pub struct Keymap {
+    bindings: HashMap<Mode, Vec<(Vec<String>, Action)>>,
+    mode: Mode,
+    pending: Vec<String>,
+    count: Option<u32>,
+    // When to stop waiting for the rest of a sequence
+    deadline: Option<Instant>,
+}
+
+impl Keymap {
+    /// Reads the bindings file on top of the defaults.
+    pub fn load(path: &Path) -> Self {
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            count: None,
+            deadline: None,
+        };
+        keymap.parse(DEFAULT_BINDINGS, "default bindings");
+        if let Ok(contents) = fs::read_to_string(path) {
+            keymap.parse(&contents, &path.display().to_string());
+        }
+        keymap
+    }
+
+    fn parse(&mut self, contents: &str, source: &str) {
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (modes, keys, action) = match fields.as_slice() {
+                [modes, keys, action] => (modes, keys, action),
+                _ => {
+                    println!("{}:{}: expected modes, keys and an action", source, n + 1);
+                    continue;
+                },
+            };
+            let action = match Action::from_name(action) {
+                Some(a) => a,
+                None => {
+                    println!("{}:{}: unknown action {:?}", source, n + 1, action);
+                    continue;
+                },
+            };
+            let keys = match parse_keys(keys) {
+                Ok(k) => k,
+                Err(e) => {
+                    println!("{}:{}: {}", source, n + 1, e);
+                    continue;
+                },
+            };
+            for name in modes.split(',') {
+                match Mode::from_name(name) {
+                    Some(mode) => {
+                        let bindings = self.bindings.entry(mode).or_default();
+                        bindings.retain(|(k, _)| *k != keys);
+                        bindings.push((keys.clone(), action));
+                    },
+                    None => println!("{}:{}: unknown mode {:?}", source, n + 1, name),
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+        self.count = None;
+        self.deadline = None;
+    }
+
+    fn exact(&self) -> Option<Action> {
+        self.bindings.get(&self.mode)?.iter().find(|(keys, _)| *keys == self.pending).map(|(_, a)| *a)
+    }
+
+    pub fn feed(&mut self, mode: Mode, key: String, now: Instant) -> KeyResult {
+        if mode != self.mode {
+            self.reset();
+            self.mode = mode;
+        }
+        // Counts, a leading 0 is a key like any other
+        let counts = matches!(mode, Mode::Normal | Mode::Visual) && self.pending.is_empty();
+        if let Some(digit) = key.chars().next().and_then(|c| c.to_digit(10)).filter(|_| counts && key.len() == 1) {
+            if digit > 0 || self.count.is_some() {
+                self.count = Some((self.count.unwrap_or(0) * 10 + digit).min(MAX_COUNT));
+                return KeyResult::Pending;
+            }
+        }
+
+        self.pending.push(key);
+        let exact = self.exact();
+        let longer = self.bindings.get(&mode).is_some_and(|bindings| bindings.iter()
+            .any(|(keys, _)| keys.len() > self.pending.len() && keys.starts_with(&self.pending)));
+        match (exact, longer) {
+            (Some(action), false) => {
+                let count = self.count.unwrap_or(1);
+                self.reset();
+                KeyResult::Action(action, count)
+            },
+            // Either a prefix of something longer, or a binding that is also one; the timeout
+            // decides which
+            (_, true) => {
+                self.deadline = Some(now + SEQUENCE_TIMEOUT);
+                KeyResult::Pending
+            },
+            (None, false) => {
+                // A broken sequence swallows its keys like vim, a lone key falls through
+                let lone = self.pending.len() == 1 && self.count.is_none();
+                self.reset();
+                if lone { KeyResult::Unbound } else { KeyResult::Pending }
+            },
+        }
+    }
+
+    /// Looks up a key pressed while a panel has the keyboard, for the few actions that work
+    /// everywhere.
+    pub fn global(&self, key: &str) -> Option<Action> {
+        self.bindings.get(&Mode::Normal)?.iter()
+            .find(|(keys, action)| keys.len() == 1 && keys[0] == key && action.is_global())
+            .map(|(_, a)| *a)
+    }
+
+    /// When a pending sequence times out, if one is waiting.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Gives up waiting for the rest of a sequence, running what was typed if it is a binding of
+    /// its own.
+    pub fn timeout(&mut self, now: Instant) -> Option<(Action, u32)> {
+        if self.deadline.is_none_or(|d| now < d) {
+            return None;
+        }
+        let action = self.exact();
+        let count = self.count.unwrap_or(1);
+        self.reset();
+        action.map(|a| (a, count))
+    }
+
+    /// Draws the mode and the keys typed so far in the bottom right corner, like vim's showcmd.
+    pub fn draw_status(&self, screen: &mut Screen, mode: Mode, theme: &Theme) {
+        let mut status = String::new();
+        if mode == Mode::Visual {
+            status.push_str("-- VISUAL --");
+        }
+        if mode == self.mode && (self.count.is_some() || !self.pending.is_empty()) {
+            if !status.is_empty() {
+                status.push_str("  ");
+            }
+            if let Some(count) = self.count {
+                status.push_str(&count.to_string());
+            }
+            status.extend(self.pending.iter().map(String::as_str));
+        }
+        if status.is_empty() {
+            return;
+        }
+        let len = status.chars().count() as u32 + 2;
+        let row = screen.rows().saturating_sub(1);
+        let col = screen.cols().saturating_sub(len);
+        screen.fill(row, col, len, theme.panel());
+        screen.print_colored(row, col + 1, &status, theme.accent(), theme.panel());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap() -> Keymap {
+        Keymap::load(Path::new("/nonexistent/keys"))
+    }
+
+    // Feeds keys one at a time, returning the action the last one ran, if any
+    fn run(keymap: &mut Keymap, mode: Mode, keys: &[&str], now: Instant) -> Option<(Action, u32)> {
+        let mut result = None;
+        for key in keys {
+            result = match keymap.feed(mode, key.to_string(), now) {
+                KeyResult::Action(action, count) => Some((action, count)),
+                KeyResult::Pending | KeyResult::Unbound => None,
+            };
+        }
+        result
+    }
+
+    #[test]
+    fn bracketed_names_are_normalised() {
+        assert_eq!(parse_bracketed("c-D").as_deref(), Some("<C-d>"));
+        assert_eq!(parse_bracketed("C-d").as_deref(), Some("<C-d>"));
+        assert_eq!(parse_bracketed("M-x").as_deref(), Some("<A-x>"));
+        assert_eq!(parse_bracketed("escape").as_deref(), Some("<Esc>"));
+        assert_eq!(parse_bracketed("s-tab").as_deref(), Some("<S-Tab>"));
+        assert_eq!(parse_bracketed("f2").as_deref(), Some("<F2>"));
+        assert_eq!(parse_bracketed("lt").as_deref(), Some("<lt>"));
+        assert_eq!(parse_bracketed("X-a"), None);
+        assert_eq!(parse_bracketed("bogus"), None);
+    }
+
+    #[test]
+    fn key_sequences_split_into_keys() {
+        assert_eq!(parse_keys("gg").unwrap(), ["g", "g"]);
+        assert_eq!(parse_keys("<C-w>j").unwrap(), ["<C-w>", "j"]);
+        assert_eq!(parse_keys("<").unwrap(), ["<lt>"]);
+        assert_eq!(parse_keys("<>").unwrap(), ["<lt>", ">"]);
+        assert!(parse_keys("<nope>").is_err());
+        assert!(parse_keys("").is_err());
+    }
+
+    #[test]
+    fn counts_come_before_actions() {
+        let now = Instant::now();
+        let mut keymap = keymap();
+        assert_eq!(run(&mut keymap, Mode::Normal, &["j"], now), Some((Action::Down, 1)));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["5", "j"], now), Some((Action::Down, 5)));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["1", "0", "k"], now), Some((Action::Up, 10)));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["9", "9", "9", "9", "9", "j"], now), Some((Action::Down, MAX_COUNT)));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["3", "g", "g"], now), Some((Action::Top, 3)));
+        // A leading 0 isn't a count, and digits are text in insert mode
+        assert!(matches!(keymap.feed(Mode::Normal, "0".to_string(), now), KeyResult::Unbound));
+        assert!(matches!(keymap.feed(Mode::Insert, "5".to_string(), now), KeyResult::Unbound));
+    }
+
+    #[test]
+    fn sequences_wait_for_their_keys() {
+        let now = Instant::now();
+        let mut keymap = keymap();
+        assert!(matches!(keymap.feed(Mode::Normal, "g".to_string(), now), KeyResult::Pending));
+        assert_eq!(keymap.deadline(), Some(now + SEQUENCE_TIMEOUT));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["g"], now), Some((Action::Top, 1)));
+        assert_eq!(keymap.deadline(), None);
+
+        // A broken sequence swallows its keys, the next one starts afresh
+        assert_eq!(run(&mut keymap, Mode::Normal, &["g", "x"], now), None);
+        assert_eq!(run(&mut keymap, Mode::Normal, &["j"], now), Some((Action::Down, 1)));
+
+        // Switching modes drops a half typed sequence
+        keymap.feed(Mode::Normal, "g".to_string(), now);
+        assert_eq!(run(&mut keymap, Mode::Visual, &["j"], now), Some((Action::Down, 1)));
+    }
+
+    #[test]
+    fn timeout_runs_a_binding_that_is_also_a_prefix() {
+        let now = Instant::now();
+        let mut keymap = keymap();
+        keymap.parse("normal  y  mark", "test");
+        assert!(matches!(keymap.feed(Mode::Normal, "y".to_string(), now), KeyResult::Pending));
+        assert_eq!(keymap.timeout(now), None);
+        assert_eq!(keymap.timeout(now + SEQUENCE_TIMEOUT), Some((Action::Mark, 1)));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["y", "y"], now), Some((Action::Copy, 1)));
+
+        // A prefix of nothing but longer bindings runs nothing
+        keymap.feed(Mode::Normal, "g".to_string(), now);
+        assert_eq!(keymap.timeout(now + SEQUENCE_TIMEOUT), None);
+    }
+
+    #[test]
+    fn user_bindings_override_defaults() {
+        let now = Instant::now();
+        let mut keymap = keymap();
+        keymap.parse("normal,visual  j  nop\nnormal  <c-N>  down\nnormal  x  nonsense", "test");
+        assert_eq!(run(&mut keymap, Mode::Normal, &["j"], now), Some((Action::Nop, 1)));
+        assert_eq!(run(&mut keymap, Mode::Visual, &["j"], now), Some((Action::Nop, 1)));
+        assert_eq!(run(&mut keymap, Mode::Normal, &["<C-n>"], now), Some((Action::Down, 1)));
+        assert!(matches!(keymap.feed(Mode::Normal, "x".to_string(), now), KeyResult::Unbound));
+    }
+
+    #[test]
+    fn global_keys_only_reach_global_actions() {
+        let keymap = keymap();
+        assert_eq!(keymap.global("<C-f>"), Some(Action::HistorySearch));
+        assert_eq!(keymap.global("j"), None);
+    }
+}
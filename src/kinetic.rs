@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+// How quickly a flick slows down, as an exponential decay rate a second
+const FRICTION: f32 = 4.0;
+// Slower than this in pixels a second and it stops
+const MIN_SPEED: f32 = 30.0;
+// Deltas further apart than this aren't part of the same movement
+const GAP: Duration = Duration::from_millis(100);
+
+/// Keeps a touchpad scroll going after the fingers lift, slowing down as if it had momentum.
+/// Speeds are in pixels a second, positive towards the newest messages.
+pub struct Kinetic {
+    velocity: f32,
+    last: Option<Instant>,
+    coasting: bool,
+}
+
+impl Kinetic {
+    pub fn new() -> Self {
+        Self {
+            velocity: 0.0,
+            last: None,
+            coasting: false,
+        }
+    }
+
+    /// A scroll by the fingers, tracking how fast they are going.
+    pub fn track(&mut self, pixels: f32, now: Instant) {
+        self.coasting = false;
+        self.velocity = match self.last.map(|last| now.duration_since(last)) {
+            // Averaged with the last speed, single deltas are jittery
+            Some(dt) if dt < GAP => self.velocity * 0.5 + pixels / dt.as_secs_f32().max(0.001) * 0.5,
+            _ => 0.0,
+        };
+        self.last = Some(now);
+    }
+
+    /// The fingers lifted, carry on at the speed they were going.
+    pub fn release(&mut self, now: Instant) {
+        let recent = self.last.is_some_and(|last| now.duration_since(last) < GAP);
+        self.coasting = recent && self.velocity.abs() > MIN_SPEED;
+        self.last = Some(now);
+    }
+
+    pub fn stop(&mut self) {
+        self.velocity = 0.0;
+        self.last = None;
+        self.coasting = false;
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.coasting
+    }
+
+    /// How far to scroll since the last step, None once it has stopped.
+    pub fn step(&mut self, now: Instant) -> Option<f32> {
+        if !self.coasting {
+            return None;
+        }
+        let dt = now.duration_since(self.last?).as_secs_f32();
+        self.last = Some(now);
+        let decay = (-FRICTION * dt).exp();
+        // The distance covered while slowing from one speed to the other
+        let pixels = self.velocity * (1.0 - decay) / FRICTION;
+        self.velocity *= decay;
+        if self.velocity.abs() < MIN_SPEED {
+            self.stop();
+        }
+        Some(pixels)
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::time::{Instant, Duration};
 use winit::{
@@ -6,14 +7,179 @@ use winit::{
     window::WindowBuilder,
 };
 use tokio::runtime::Builder;
+use tokio::sync::mpsc::{self, UnboundedSender};
 use ringbuf::RingBuffer;
+use regex::Regex;
 use crate::renderer::Screen;
+use crate::chat::{ChatEvent, ChatMessage, MessageKind};
+use crate::bulk::{BulkAction, BulkPreview, ModQueue};
+use crate::chatlog::{ChatLogger, LogConfig};
+use crate::chatters::{ChatterPanel, Chatters};
+use crate::commands::{Change, CommandHistory, ExCommand, Setting};
+use crate::completion::{Completer, Completion, InputHistory};
+use crate::echo::LocalEcho;
+use crate::filter::{Action, Filter};
+use crate::find::FindPrompt;
+use crate::hints::{HintAction, HintMode, HintResult};
+use crate::history::{History, HistoryWriter};
+use crate::input::InputLine;
+use crate::keys::{Action as KeyAction, KeyResult, Keymap, Mode};
+use crate::kinetic::Kinetic;
+use crate::moderation::Outcome;
+use crate::newcomers::Newcomers;
+use crate::paid::{Rewards, Special};
+use crate::results::ResultsView;
+use crate::spam::SpamDetector;
+use crate::spell::SpellChecker;
+use crate::stats::{Stats, StatsView};
+use crate::template::{StyledLine, Template};
+use crate::theme::{Theme, THEMES};
+use crate::usercard::UserCard;
+use crate::view::{ChatView, DIM, ERROR, ERROR_BG, NORMAL, SUCCESS};
 
+mod bulk;
 mod chat;
+mod chatlog;
+mod chatters;
+mod commands;
+mod completion;
+mod config;
+mod echo;
+mod emotes;
+mod export;
+mod filter;
+mod find;
+mod hints;
+mod history;
+mod input;
+mod keys;
+mod kinetic;
+mod moderation;
+mod newcomers;
+mod paid;
 mod renderer;
+mod results;
+mod spam;
+mod spell;
+mod stats;
+mod template;
+mod theme;
+mod usercard;
+mod view;
+
+const SEARCH_LIMIT: usize = 500;
+// How far back a regex selection looks for messages to mark
+const SELECT_LIMIT: usize = 500;
+// Rows the chat scrolls for each notch of a mouse wheel
+const WHEEL_ROWS: f32 = 3.0;
+// How often to redraw while the chat is moving by itself
+const FRAME: Duration = Duration::from_millis(16);
+
+const SPECIAL_FG: [f32; 4] = [1.0, 0.85, 0.4, 1.0];
+const MISSPELLED: [f32; 3] = [0.9, 0.25, 0.25];
+
+/// Whatever is currently drawn over the chat and receiving keyboard input.
+enum Overlay {
+    None,
+    SearchPrompt(InputLine),
+    Results(ResultsView),
+    // The message being typed, the Tab completion in progress if any and the message it replies to
+    Compose(InputLine, Option<Completion>, Option<ChatMessage>),
+    // A `:` command being typed, its Tab completion and why it last failed
+    CommandLine(InputLine, Option<Completion>, Option<String>),
+    Find(FindPrompt),
+    SelectPrompt(InputLine),
+    BulkPreview(BulkPreview),
+    UserCard(UserCard),
+    Chatters(ChatterPanel),
+    Stats(StatsView),
+    Hints(HintMode),
+}
+
+/// The keymap mode for whatever has the keyboard, None while a panel like the user card has it.
+fn key_mode(overlay: &Overlay, view: &ChatView) -> Option<Mode> {
+    match overlay {
+        Overlay::None if view.in_visual() => Some(Mode::Visual),
+        Overlay::None => Some(Mode::Normal),
+        Overlay::Compose(..) => Some(Mode::Insert),
+        Overlay::CommandLine(..) => Some(Mode::Command),
+        _ => None,
+    }
+}
+
+/// Runs what a hint rule says to do with a link, or copies it.
+fn run_hint(link: &hints::Link, copy: bool, rules: &[hints::HintRule], view: &mut ChatView) {
+    let action = match rules.get(link.rule) {
+        Some(rule) if !copy => &rule.action,
+        _ => &HintAction::Copy,
+    };
+    if let Err(e) = action.run(&link.text) {
+        view.push_colored(e, ERROR, ERROR_BG);
+    }
+}
+
+/// Sends a line typed into the compose prompt, either as a moderation command or as chat. Chat
+/// shows up straight away as pending until Twitch confirms it, and a reply goes to the channel
+/// of the message it answers.
+fn send_line(
+    line: &str,
+    channel: &str,
+    reply: Option<&ChatMessage>,
+    outbound: &UnboundedSender<String>,
+    view: &mut ChatView,
+    echo: &mut LocalEcho,
+    template: &Template,
+) {
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+    let channel = reply.map_or(channel, |m| m.channel.as_str());
+    let (irc, nonce, tags) = match moderation::Command::parse(line) {
+        Some(Ok(command)) => (command.to_irc(channel), None, HashMap::new()),
+        Some(Err(usage)) => {
+            view.push_colored(usage, ERROR, ERROR_BG);
+            return;
+        },
+        None => {
+            let nonce = LocalEcho::nonce();
+            // Twitch threads a message under the one named in reply-parent-msg-id and fills in
+            // the rest of the reply-parent tags for everyone else, we fill them in for our echo
+            let tags: HashMap<String, String> = reply.map(|m| HashMap::from([
+                ("reply-parent-msg-id".to_string(), m.id.clone()),
+                ("reply-parent-user-login".to_string(), m.sender.clone()),
+                ("reply-parent-display-name".to_string(), m.display_name().to_string()),
+                ("reply-parent-msg-body".to_string(), m.message.clone()),
+            ])).unwrap_or_default();
+            let client_tags = match reply {
+                Some(m) => format!("client-nonce={};reply-parent-msg-id={}", nonce, m.id),
+                None => format!("client-nonce={}", nonce),
+            };
+            (format!("@{} PRIVMSG #{} :{}", client_tags, channel, line), Some(nonce), tags)
+        },
+    };
+    if outbound.send(irc).is_err() {
+        view.push_colored("Not connected to chat".to_string(), ERROR, ERROR_BG);
+        return;
+    }
+    match nonce {
+        Some(nonce) => echo.sent(nonce, channel, line, tags, view, template),
+        None => { view.push(format!("» {}", line), DIM); },
+    }
+}
 
 fn main() {
     env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("export") {
+        if let Err(e) = export::run(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -41,18 +207,450 @@ fn main() {
     };
 
     // TODO: Replace this ring buffer, it doesn't actually work the way I want: overwriting input
-    // as it comes in.
-    let rb = RingBuffer::<String>::new(20);
+    // as it comes in. It needs room for a whole batch of JOIN/PARTs, Twitch sends those every ten
+    // seconds or so.
+    let rb = RingBuffer::<ChatEvent>::new(1024);
     let (prod, mut cons) = rb.split();
 
-    if token != "" && nick != "" {
-        let _handle = runtime.spawn(chat::read_chat(token, nick, prod));
+    // Several channels can be joined at once, e.g. CHANNEL=one,two, and are shown merged
+    let mut channels: Vec<String> = env::var("CHANNEL").unwrap_or_default()
+        .split(',')
+        .map(|c| c.trim().trim_start_matches('#').to_lowercase())
+        .filter(|c| !c.is_empty())
+        .collect();
+    if channels.is_empty() {
+        channels.push("bnans".to_string());
     }
+    // The channel the input line sends to
+    let mut target = 0;
 
-    let mut l = 0;
+    let (outbound, outbound_rx) = mpsc::unbounded_channel();
+    let mut echo = LocalEcho::new(&nick);
+    // Kept for finding mentions, the nick itself goes to the chat task
+    let own_nick = nick.clone();
+    if !token.is_empty() && !nick.is_empty() {
+        let _handle = runtime.spawn(chat::read_chat(token, nick, channels.clone(), prod, outbound_rx));
+    }
+
+    let mut filter = Filter::load(config::config_dir().join("filters"));
+
+    let history_writer = match HistoryWriter::spawn(history::default_path()) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            println!("Failed to open history, messages will not be saved: {}", e);
+            None
+        }
+    };
+    let history = History::open(&history::default_path()).ok();
+    let mut chat_logger = LogConfig::from_env().map(ChatLogger::spawn);
+
+    let mut overlay = Overlay::None;
+    let mut keymap = Keymap::load(&config::config_dir().join("keys"));
+    let mut command_history = CommandHistory::load(config::data_dir().join("command_history"));
+    let mut theme = Theme::default();
+    let mut modifiers = ModifiersState::empty();
+    let mut mouse_position = (0.0, 0.0);
+
+    let mut mod_queue = ModQueue::new();
+    let mut spam = SpamDetector::new();
+    let mut view = ChatView::new();
+    // How many messages to keep for scrolling back through, e.g. SCROLLBACK=50000
+    if let Some(lines) = env::var("SCROLLBACK").ok().and_then(|v| v.parse().ok()) {
+        view.set_scrollback(lines);
+    }
+    view.set_smooth(env::var("SMOOTH_SCROLL").is_ok_and(|v| v == "1" || v == "true"));
+    let mut kinetic = Kinetic::new();
+    let mut last_frame = Instant::now();
+    let hint_rules = hints::load(&config::config_dir().join("hints"));
+    view.set_link_patterns(hint_rules.iter().map(|r| r.regex.clone()).collect());
+    view.set_show_channels(channels.len() > 1);
+    let mut chatters: HashMap<String, Chatters> = channels.iter().map(|c| (c.clone(), Chatters::new())).collect();
+    let mut stats = Stats::new();
+    let mut completer = Completer::new();
+    let mut input_history = InputHistory::new();
+    let mut spell = SpellChecker::load();
+    let mut spell_on = true;
+    let mut newcomers = Newcomers::from_env(history.as_ref());
+    let template = Template::load(&config::config_dir().join("template"));
+    let rewards = Rewards::load(&config::config_dir().join("rewards"));
+    // Hype Chats can stay at the top of the view for as long as Twitch pins them
+    let mut pin_paid = env::var("PIN_PAID_MESSAGES").is_ok_and(|v| v == "1" || v == "true");
 
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::WaitUntil(Instant::now() + Duration::from_millis(500));
+        let mut wake = Instant::now() + Duration::from_millis(500);
+        if let Some(ready) = mod_queue.next_ready(Instant::now()) {
+            wake = wake.min(ready);
+        }
+        if let Some(deadline) = keymap.deadline() {
+            wake = wake.min(deadline);
+        }
+        if kinetic.is_moving() || view.is_animating() {
+            wake = wake.min(Instant::now() + FRAME);
+        }
+        *control_flow = ControlFlow::WaitUntil(wake);
+
+        // Keys go through the keymap first, whatever it doesn't bind falls through to typing into
+        // prompts and driving the panels below
+        let key = match &event {
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(code),
+                        ..
+                    },
+                    ..
+                },
+                window_id,
+            } if *window_id == window.id() => keys::from_keycode(*code, modifiers),
+            // Characters are text in insert and command mode
+            Event::WindowEvent {
+                event: WindowEvent::ReceivedCharacter(c),
+                window_id,
+            } if *window_id == window.id() && matches!(key_mode(&overlay, &view), Some(Mode::Normal | Mode::Visual)) => {
+                keys::from_char(*c, modifiers)
+            },
+            _ => None,
+        };
+        let mut fired = match key {
+            Some(key) => {
+                let result = match key_mode(&overlay, &view) {
+                    Some(mode) => keymap.feed(mode, key, Instant::now()),
+                    None => keymap.global(&key).map_or(KeyResult::Unbound, |action| KeyResult::Action(action, 1)),
+                };
+                match result {
+                    KeyResult::Action(action, count) => Some((action, count)),
+                    KeyResult::Pending => {
+                        window.request_redraw();
+                        return;
+                    },
+                    KeyResult::Unbound => None,
+                }
+            },
+            None if matches!(event, Event::NewEvents(StartCause::ResumeTimeReached { .. })) => keymap.timeout(Instant::now()),
+            None => None,
+        };
+        let ran = fired.is_some();
+        while let Some((action, count)) = fired.take() {
+            let n = count as i32;
+            // Pages count messages as rows, which is right for the one line messages most are
+            let page = (screen.rows() as i32).max(2);
+            match action {
+                KeyAction::Nop => {},
+                KeyAction::Down => view.move_cursor(n),
+                KeyAction::Up => view.move_cursor(-n),
+                KeyAction::HalfPageDown => view.move_cursor(n * page / 2),
+                KeyAction::HalfPageUp => view.move_cursor(-n * page / 2),
+                KeyAction::PageDown => view.move_cursor(n * page),
+                KeyAction::PageUp => view.move_cursor(-n * page),
+                KeyAction::Top => view.move_cursor(i32::MIN),
+                KeyAction::Bottom => view.move_cursor(i32::MAX),
+                KeyAction::ScrollDown => view.scroll_by(n, &screen),
+                KeyAction::ScrollUp => view.scroll_by(-n, &screen),
+                KeyAction::ScrollPageDown => view.scroll_pages(n, &screen),
+                KeyAction::ScrollPageUp => view.scroll_pages(-n, &screen),
+                KeyAction::ScrollBottom => view.scroll_to_bottom(),
+                KeyAction::NextMention | KeyAction::PreviousMention => {
+                    let forward = action == KeyAction::NextMention;
+                    let jumped = (0..count).take_while(|_| view.jump_to(forward, |m| m.mentions(&own_nick))).count();
+                    if jumped == 0 {
+                        view.push(format!("No {} mentions of {}", if forward { "newer" } else { "older" }, own_nick), DIM);
+                    }
+                },
+                KeyAction::Search => overlay = Overlay::Find(FindPrompt::new(&view)),
+                // Searches run from newest to oldest, so n carries on to older messages
+                KeyAction::NextMatch | KeyAction::PreviousMatch => {
+                    let forward = action == KeyAction::PreviousMatch;
+                    if !view.has_search() {
+                        view.push("Nothing searched for yet, / starts a search".to_string(), DIM);
+                    } else if (0..count).take_while(|_| view.next_match(forward)).count() == 0 {
+                        view.push(format!("No {} matches", if forward { "newer" } else { "older" }), DIM);
+                    }
+                },
+                KeyAction::UserCard => {
+                    if let Some(m) = view.cursor_message().filter(|m| !m.sender.is_empty()) {
+                        overlay = Overlay::UserCard(UserCard::new(m, history.as_ref()));
+                    }
+                },
+                KeyAction::Reply => {
+                    if let Some(m) = view.cursor_message().filter(|m| !m.id.is_empty() && !m.sender.is_empty()) {
+                        overlay = Overlay::Compose(InputLine::new(), None, Some(m.clone()));
+                    }
+                },
+                KeyAction::Copy => {
+                    let lines: Vec<String> = view.selected_messages().iter().map(|m| template.render(m).text).collect();
+                    if lines.is_empty() {
+                        view.push("Nothing selected to copy".to_string(), DIM);
+                    } else if let Err(e) = HintAction::Copy.run(&lines.join("\n")) {
+                        view.push_colored(e, ERROR, ERROR_BG);
+                    } else {
+                        view.push(format!("Copied {} messages", lines.len()), DIM);
+                    }
+                    view.end_visual();
+                },
+                KeyAction::Mark => view.toggle_mark(),
+                KeyAction::Timeout | KeyAction::Ban => {
+                    if view.has_selection() {
+                        let action = if action == KeyAction::Timeout { BulkAction::Timeout } else { BulkAction::Ban };
+                        overlay = Overlay::BulkPreview(BulkPreview::new(action, &view.selected_messages()));
+                    }
+                },
+                KeyAction::Insert => overlay = Overlay::Compose(InputLine::new(), None, None),
+                KeyAction::Visual => view.start_visual(),
+                KeyAction::CommandLine => {
+                    command_history.reset();
+                    overlay = Overlay::CommandLine(InputLine::new(), None, None);
+                },
+                // Backs out one step at a time: the completion, then the reply, then the prompt
+                KeyAction::Normal => match &mut overlay {
+                    Overlay::Compose(prompt, completion @ Some(_), _) | Overlay::CommandLine(prompt, completion @ Some(_), _) => {
+                        if let Some(completion) = completion.take() {
+                            completion.cancel(prompt);
+                        }
+                    },
+                    Overlay::Compose(_, _, reply) if reply.is_some() => *reply = None,
+                    Overlay::None => view.end_visual(),
+                    _ => overlay = Overlay::None,
+                },
+                KeyAction::Escape if view.has_selection() || view.has_search() => {
+                    view.clear_selection();
+                    view.set_search(None);
+                },
+                KeyAction::Escape | KeyAction::Quit => *control_flow = ControlFlow::Exit,
+                KeyAction::HistorySearch => overlay = Overlay::SearchPrompt(InputLine::new()),
+                KeyAction::SelectRegex => overlay = Overlay::SelectPrompt(InputLine::new()),
+                KeyAction::Hints => {
+                    // Labels go on whole rows
+                    kinetic.stop();
+                    view.snap(&screen);
+                    let mode = HintMode::new(view.links(&screen));
+                    if mode.is_empty() {
+                        view.push("No links on screen".to_string(), DIM);
+                    } else {
+                        overlay = Overlay::Hints(mode);
+                    }
+                },
+                KeyAction::Mute => {
+                    let channel = &channels[target];
+                    if view.toggle_mute(channel) {
+                        view.push(format!("Muted #{}", channel), DIM);
+                    } else {
+                        view.push(format!("Unmuted #{}", channel), DIM);
+                    }
+                },
+                KeyAction::NextChannel if channels.len() > 1 => {
+                    target = (target + 1) % channels.len();
+                    input_history.reset();
+                    if matches!(overlay, Overlay::None) {
+                        view.push(format!("Now typing in #{}", channels[target]), DIM);
+                    }
+                },
+                KeyAction::NextChannel => {},
+                KeyAction::Chatters => overlay = Overlay::Chatters(ChatterPanel::new()),
+                KeyAction::Stats => overlay = Overlay::Stats(StatsView::new()),
+                KeyAction::ReloadFilters => {
+                    filter.reload();
+                    for (rule, suppressed) in filter.stats() {
+                        println!("{:>8}  {}", suppressed, rule);
+                    }
+                },
+                // The command line completes and recalls commands rather than chat
+                KeyAction::Complete
+                | KeyAction::CompletePrevious
+                | KeyAction::HistoryPrevious
+                | KeyAction::HistoryNext if matches!(overlay, Overlay::CommandLine(..)) => {
+                    let (prompt, completion, error) = match &mut overlay {
+                        Overlay::CommandLine(prompt, completion, error) => (prompt, completion, error),
+                        _ => continue,
+                    };
+                    *error = None;
+                    match (action, completion.as_mut()) {
+                        (KeyAction::Complete | KeyAction::HistoryNext, Some(c)) => c.cycle(1, prompt),
+                        (KeyAction::CompletePrevious | KeyAction::HistoryPrevious, Some(c)) => c.cycle(-1, prompt),
+                        (KeyAction::Complete | KeyAction::CompletePrevious, None) => {
+                            let (start, candidates) = commands::candidates(&prompt.text()[..prompt.cursor()], &channels);
+                            *completion = Completion::with_candidates(prompt, start, candidates);
+                        },
+                        (KeyAction::HistoryPrevious, None) => command_history.step(-1, prompt),
+                        _ => command_history.step(1, prompt),
+                    }
+                },
+                KeyAction::Send
+                | KeyAction::Complete
+                | KeyAction::CompletePrevious
+                | KeyAction::HistoryPrevious
+                | KeyAction::HistoryNext
+                | KeyAction::SpellSuggest => {
+                    let (prompt, completion, reply) = match &mut overlay {
+                        Overlay::Compose(prompt, completion, reply) => (prompt, completion, reply),
+                        _ => continue,
+                    };
+                    let channel = &channels[target];
+                    match (action, completion.as_mut()) {
+                        // Enter takes the candidate rather than sending
+                        (KeyAction::Send, Some(_)) => *completion = None,
+                        (KeyAction::Send, None) => {
+                            // Stay in the prompt for the next message, Escape leaves it
+                            let channel = reply.as_ref().map_or(channel, |m| &m.channel);
+                            send_line(prompt.text(), channel, reply.as_ref(), &outbound, &mut view, &mut echo, &template);
+                            input_history.record(channel, prompt.text());
+                            prompt.clear();
+                            *reply = None;
+                        },
+                        (KeyAction::Complete | KeyAction::HistoryNext, Some(c)) => c.cycle(1, prompt),
+                        (KeyAction::CompletePrevious | KeyAction::HistoryPrevious, Some(c)) => c.cycle(-1, prompt),
+                        (KeyAction::Complete | KeyAction::CompletePrevious, None) => {
+                            *completion = Completion::start(prompt, &completer, channel);
+                        },
+                        (KeyAction::HistoryPrevious, None) => input_history.step(channel, -1, prompt),
+                        (KeyAction::HistoryNext, None) => input_history.step(channel, 1, prompt),
+                        // Suggestions for the nearest misspelling, picked like completions
+                        _ => {
+                            let fix = spell.as_ref().filter(|_| spell_on).and_then(|s| Some((s, s.word_to_fix(prompt.text(), prompt.cursor())?)));
+                            if let Some((spell, range)) = fix {
+                                let suggestions = spell.suggest(&prompt.text()[range.clone()]);
+                                if suggestions.is_empty() {
+                                    view.push(format!("No suggestions for {}", &prompt.text()[range]), DIM);
+                                } else {
+                                    prompt.set_cursor(range.end);
+                                    *completion = Completion::with_candidates(prompt, range.start, suggestions);
+                                }
+                            }
+                        },
+                    }
+                },
+                KeyAction::Execute => {
+                    let line = match &overlay {
+                        Overlay::CommandLine(prompt, ..) => prompt.text().trim().to_string(),
+                        _ => continue,
+                    };
+                    overlay = Overlay::None;
+                    if line.is_empty() {
+                        continue;
+                    }
+                    command_history.record(&line);
+                    let result = match ExCommand::parse(&line) {
+                        Err(e) => Err(e),
+                        Ok(ExCommand::Action(action, count)) => {
+                            fired = Some((action, count));
+                            Ok(())
+                        },
+                        Ok(ExCommand::Join(channel)) if channels.contains(&channel) => Err(format!("Already in #{}", channel)),
+                        Ok(ExCommand::Join(channel)) => {
+                            if outbound.send(format!("JOIN #{}", channel)).is_err() {
+                                Err("Not connected to chat".to_string())
+                            } else {
+                                view.push(format!("Joined #{}", channel), DIM);
+                                chatters.insert(channel.clone(), Chatters::new());
+                                channels.push(channel);
+                                view.set_show_channels(channels.len() > 1);
+                                Ok(())
+                            }
+                        },
+                        Ok(ExCommand::Part(channel)) => {
+                            let channel = channel.unwrap_or_else(|| channels[target].clone());
+                            match channels.iter().position(|c| *c == channel) {
+                                None => Err(format!("Not in #{}", channel)),
+                                Some(_) if channels.len() == 1 => Err("Can't leave the only channel".to_string()),
+                                Some(_) if outbound.send(format!("PART #{}", channel)).is_err() => Err("Not connected to chat".to_string()),
+                                Some(i) => {
+                                    channels.remove(i);
+                                    chatters.remove(&channel);
+                                    // Keep typing where we were, unless that's the channel we left
+                                    if target > i {
+                                        target -= 1;
+                                    }
+                                    target %= channels.len();
+                                    input_history.reset();
+                                    view.set_show_channels(channels.len() > 1);
+                                    view.push(format!("Left #{}, now typing in #{}", channel, channels[target]), DIM);
+                                    Ok(())
+                                },
+                            }
+                        },
+                        Ok(ExCommand::Theme(Some(chosen))) => {
+                            theme = chosen;
+                            view.set_theme(theme);
+                            screen.set_clear_color(theme.clear());
+                            Ok(())
+                        },
+                        Ok(ExCommand::Theme(None)) => {
+                            let names: Vec<&str> = THEMES.iter().map(|t| t.name).collect();
+                            view.push(format!("Using {}, themes are {}", theme.name, names.join(", ")), DIM);
+                            Ok(())
+                        },
+                        Ok(ExCommand::Set(settings)) => {
+                            let mut shown = Vec::new();
+                            let mut result = Ok(());
+                            for (setting, change) in settings {
+                                let current = match setting {
+                                    Setting::FontSize => None,
+                                    Setting::Filters => Some(filter.is_enabled()),
+                                    Setting::Spell => Some(spell_on && spell.is_some()),
+                                    Setting::PinPaid => Some(pin_paid),
+                                    Setting::Channels => Some(view.shows_channels()),
+                                    Setting::SmoothScroll => Some(view.is_smooth()),
+                                };
+                                let on = match (change, current) {
+                                    (Change::Show, Some(on)) => {
+                                        shown.push(format!("{}{}", if on { "" } else { "no" }, setting.name()));
+                                        continue;
+                                    },
+                                    (Change::Show, None) => {
+                                        shown.push(format!("{}={}", setting.name(), screen.font_size()));
+                                        continue;
+                                    },
+                                    (Change::Value(size), _) => {
+                                        if let Err(e) = screen.set_font_size(size) {
+                                            result = Err(e);
+                                        }
+                                        continue;
+                                    },
+                                    (Change::Turn(on), _) => on,
+                                    (Change::Toggle, current) => !current.unwrap_or(false),
+                                };
+                                match setting {
+                                    Setting::Spell if on && spell.is_none() => {
+                                        result = Err("No dictionary to check spelling with, see SPELL_LANG".to_string());
+                                    },
+                                    Setting::Spell => spell_on = on,
+                                    Setting::Filters => filter.set_enabled(on),
+                                    Setting::PinPaid => pin_paid = on,
+                                    Setting::Channels => view.set_show_channels(on),
+                                    Setting::SmoothScroll => view.set_smooth(on),
+                                    Setting::FontSize => {},
+                                }
+                            }
+                            if !shown.is_empty() {
+                                view.push(shown.join("  "), DIM);
+                            }
+                            result
+                        },
+                        Ok(ExCommand::Help(topic)) => commands::help(topic.as_deref()).map(|lines| {
+                            for line in lines {
+                                view.push(line, DIM);
+                            }
+                        }),
+                    };
+                    // A failed command stays on the line with the reason, ready to fix
+                    if let Err(e) = result {
+                        overlay = Overlay::CommandLine(InputLine::with_text(&line), None, Some(e));
+                    }
+                },
+            }
+            if action.moves_cursor() {
+                view.reveal_cursor(&screen);
+            }
+        }
+        if ran {
+            // Keys take over from a flick still coasting
+            kinetic.stop();
+            window.request_redraw();
+            // The timer still has chat to read
+            if !matches!(event, Event::NewEvents(_)) {
+                return;
+            }
+        }
 
         match event {
             Event::WindowEvent {
@@ -60,15 +658,264 @@ fn main() {
                 window_id,
             } if window_id == window.id() =>  {
                 match event {
-                    WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input: KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    WindowEvent::ModifiersChanged(m) => modifiers = *m,
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                        ..
+                    } => {
+                        match &mut overlay {
+                            Overlay::UserCard(card) if card.is_editing() => card.cancel_edit(),
+                            Overlay::Find(find) => {
+                                find.cancel(&mut view);
+                                view.reveal_cursor(&screen);
+                                overlay = Overlay::None;
                             },
+                            _ => overlay = Overlay::None,
+                        }
+                        window.request_redraw();
+                    },
+                    WindowEvent::ReceivedCharacter(c) => {
+                        if let Overlay::Compose(prompt, completion, _) = &mut overlay {
+                            // Typing takes the candidate and carries on from it
+                            if !c.is_control() {
+                                *completion = None;
+                            }
+                            prompt.insert(*c);
+                            window.request_redraw();
+                        } else if let Overlay::CommandLine(prompt, completion, error) = &mut overlay {
+                            if !c.is_control() {
+                                *completion = None;
+                                *error = None;
+                            }
+                            prompt.insert(*c);
+                            window.request_redraw();
+                        } else if let Overlay::Find(find) = &mut overlay {
+                            find.input.insert(*c);
+                            find.update(&mut view);
+                            view.reveal_cursor(&screen);
+                            window.request_redraw();
+                        } else if let Overlay::SearchPrompt(prompt) | Overlay::SelectPrompt(prompt) = &mut overlay {
+                            prompt.insert(*c);
+                            window.request_redraw();
+                        } else if let Overlay::Hints(mode) = &mut overlay {
+                            match mode.insert(*c) {
+                                HintResult::Pending => {},
+                                HintResult::Cancelled => overlay = Overlay::None,
+                                HintResult::Chosen(link, copy) => {
+                                    run_hint(&link, copy, &hint_rules, &mut view);
+                                    overlay = Overlay::None;
+                                },
+                            }
+                            window.request_redraw();
+                        } else if let Overlay::Stats(stats_view) = &mut overlay {
+                            match c {
+                                '+' | '=' => stats_view.change_window(1),
+                                '-' => stats_view.change_window(-1),
+                                _ => return,
+                            }
+                            window.request_redraw();
+                        } else if let Overlay::Chatters(panel) = &mut overlay {
+                            panel.filter.insert(*c);
+                            window.request_redraw();
+                        } else if let Overlay::UserCard(card) = &mut overlay {
+                            match card.editing() {
+                                Some(note) => note.insert(*c),
+                                None if *c == 'n' => card.edit_note(),
+                                None => return,
+                            }
+                            window.request_redraw();
+                        }
+                    },
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
                             ..
-                        } => *control_flow = ControlFlow::Exit,
+                        },
+                        ..
+                    } if !matches!(overlay, Overlay::None) => {
+                        if let Overlay::Compose(prompt, completion, _) = &mut overlay {
+                            if prompt.edit(*key, modifiers) {
+                                *completion = None;
+                                window.request_redraw();
+                                return;
+                            }
+                        }
+                        if let Overlay::Find(find) = &mut overlay {
+                            if *key == VirtualKeyCode::R && modifiers.ctrl() {
+                                find.toggle_regex(&mut view);
+                                window.request_redraw();
+                                return;
+                            }
+                            if find.input.edit(*key, modifiers) {
+                                find.update(&mut view);
+                                view.reveal_cursor(&screen);
+                                window.request_redraw();
+                                return;
+                            }
+                        }
+                        if let Overlay::CommandLine(prompt, completion, error) = &mut overlay {
+                            if prompt.edit(*key, modifiers) {
+                                *completion = None;
+                                *error = None;
+                                window.request_redraw();
+                                return;
+                            }
+                        }
+                        let prompt = match &mut overlay {
+                            Overlay::SearchPrompt(prompt) | Overlay::SelectPrompt(prompt) => Some(prompt),
+                            Overlay::Chatters(panel) => Some(&mut panel.filter),
+                            Overlay::UserCard(card) => card.editing(),
+                            _ => None,
+                        };
+                        if prompt.is_some_and(|prompt| prompt.edit(*key, modifiers)) {
+                            window.request_redraw();
+                            return;
+                        }
+                        match (&mut overlay, key) {
+                            (Overlay::SelectPrompt(prompt), VirtualKeyCode::Return) => {
+                                match Regex::new(prompt.text()) {
+                                    Ok(re) => {
+                                        if view.mark_matching(&re, SELECT_LIMIT) == 0 {
+                                            view.push(format!("No recent messages match {}", re), DIM);
+                                        }
+                                    },
+                                    Err(e) => { view.push_colored(e.to_string(), ERROR, ERROR_BG); },
+                                }
+                                overlay = Overlay::None;
+                            },
+                            (Overlay::BulkPreview(preview), VirtualKeyCode::Up) => preview.move_cursor(-1),
+                            (Overlay::BulkPreview(preview), VirtualKeyCode::Down) => preview.move_cursor(1),
+                            (Overlay::BulkPreview(preview), VirtualKeyCode::Space) => preview.toggle(),
+                            (Overlay::BulkPreview(preview), VirtualKeyCode::Return) => {
+                                let commands = preview.commands();
+                                for (channel, command) in &commands {
+                                    mod_queue.push(command.to_irc(channel));
+                                }
+                                view.push(format!("Queued {} moderation commands", commands.len()), DIM);
+                                view.clear_selection();
+                                overlay = Overlay::None;
+                            },
+                            // The highlights stay for n and N
+                            (Overlay::Find(find), VirtualKeyCode::Return) => {
+                                if !find.input.text().is_empty() && view.search_count() == 0 {
+                                    view.push(format!("Pattern not found: {}", find.input.text()), DIM);
+                                    find.cancel(&mut view);
+                                }
+                                overlay = Overlay::None;
+                            },
+                            (Overlay::SearchPrompt(prompt), VirtualKeyCode::Return) => {
+                                let query = prompt.text().to_string();
+                                let hits = match &history {
+                                    Some(h) => h.search(&query, SEARCH_LIMIT).unwrap_or_else(|e| {
+                                        println!("Search for {:?} failed: {}", query, e);
+                                        Vec::new()
+                                    }),
+                                    None => Vec::new(),
+                                };
+                                overlay = Overlay::Results(ResultsView::new(query, hits));
+                            },
+                            (Overlay::Hints(mode), VirtualKeyCode::Back) => mode.backspace(),
+                            (Overlay::Chatters(panel), VirtualKeyCode::Up) => panel.scroll_by(-1, &chatters[&channels[target]]),
+                            (Overlay::Chatters(panel), VirtualKeyCode::Down) => panel.scroll_by(1, &chatters[&channels[target]]),
+                            (Overlay::Chatters(panel), VirtualKeyCode::PageUp) => panel.scroll_by(-10, &chatters[&channels[target]]),
+                            (Overlay::Chatters(panel), VirtualKeyCode::PageDown) => panel.scroll_by(10, &chatters[&channels[target]]),
+                            (Overlay::Chatters(_), VirtualKeyCode::F2) => overlay = Overlay::None,
+                            (Overlay::Stats(_), VirtualKeyCode::F3) => overlay = Overlay::None,
+                            (Overlay::UserCard(card), VirtualKeyCode::Return) => card.save_note(history.as_ref()),
+                            (Overlay::UserCard(card), VirtualKeyCode::Up) => card.scroll_by(-1),
+                            (Overlay::UserCard(card), VirtualKeyCode::Down) => card.scroll_by(1),
+                            (Overlay::UserCard(card), VirtualKeyCode::PageUp) => card.scroll_by(-10),
+                            (Overlay::UserCard(card), VirtualKeyCode::PageDown) => card.scroll_by(10),
+                            (Overlay::UserCard(card), VirtualKeyCode::T | VirtualKeyCode::B | VirtualKeyCode::U | VirtualKeyCode::D)
+                                if !card.is_editing() =>
+                            {
+                                let user = card.user.clone();
+                                let command = match key {
+                                    VirtualKeyCode::T => Some((card.channel.clone(), moderation::Command::Timeout { user, seconds: moderation::DEFAULT_TIMEOUT, reason: String::new() })),
+                                    VirtualKeyCode::B => Some((card.channel.clone(), moderation::Command::Ban { user, reason: String::new() })),
+                                    VirtualKeyCode::U => Some((card.channel.clone(), moderation::Command::Unban { user })),
+                                    _ => card.latest()
+                                        .filter(|m| !m.id.is_empty())
+                                        .map(|m| (m.channel.clone(), moderation::Command::Delete { id: m.id.clone() })),
+                                };
+                                match command {
+                                    Some((channel, command)) => {
+                                        mod_queue.push(command.to_irc(&channel));
+                                        overlay = Overlay::None;
+                                    },
+                                    None => { view.push("No message to delete".to_string(), DIM); },
+                                }
+                            },
+                            (Overlay::Results(results), VirtualKeyCode::Up) => results.scroll_by(-1),
+                            (Overlay::Results(results), VirtualKeyCode::Down) => results.scroll_by(1),
+                            (Overlay::Results(results), VirtualKeyCode::PageUp) => results.scroll_by(-10),
+                            (Overlay::Results(results), VirtualKeyCode::PageDown) => results.scroll_by(10),
+                            _ => {},
+                        }
+                        window.request_redraw();
+                    },
+                    WindowEvent::MouseWheel { delta, phase, .. } if matches!(overlay, Overlay::None | Overlay::Compose(..)) => {
+                        match delta {
+                            MouseScrollDelta::LineDelta(_, y) => {
+                                kinetic.stop();
+                                view.scroll_by((-y * WHEEL_ROWS).round() as i32, &screen);
+                            },
+                            // Touchpads scroll by the pixel, and keep going for a moment when let go
+                            MouseScrollDelta::PixelDelta(p) => {
+                                let pixels = -p.y as f32;
+                                match phase {
+                                    TouchPhase::Started | TouchPhase::Cancelled => kinetic.stop(),
+                                    TouchPhase::Moved => kinetic.track(pixels, Instant::now()),
+                                    TouchPhase::Ended => kinetic.release(Instant::now()),
+                                }
+                                view.scroll_pixels(pixels, &screen);
+                            },
+                        }
+                        window.request_redraw();
+                    },
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        let lines = match delta {
+                            MouseScrollDelta::LineDelta(_, y) => -y.round() as i32,
+                            MouseScrollDelta::PixelDelta(p) => -(p.y / 20.0).round() as i32,
+                        };
+                        match &mut overlay {
+                            Overlay::Results(results) => results.scroll_by(lines),
+                            Overlay::UserCard(card) => card.scroll_by(lines),
+                            Overlay::Chatters(panel) => panel.scroll_by(lines, &chatters[&channels[target]]),
+                            _ => return,
+                        }
+                        window.request_redraw();
+                    },
+                    WindowEvent::CursorMoved { position, .. } => mouse_position = (position.x, position.y),
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } if matches!(overlay, Overlay::None) => {
+                        let (col, row) = screen.cell_at(mouse_position.0, mouse_position.1);
+                        if view.pill_at(&screen, col, row) {
+                            view.scroll_to_bottom();
+                            window.request_redraw();
+                            return;
+                        }
+                        let (col, row) = view.cell_at(&screen, mouse_position.0, mouse_position.1);
+                        if let Some(link) = view.link_at(&screen, col, row) {
+                            // A click always opens, hint rules only decide what hint mode does
+                            if let Err(e) = HintAction::Open.run(&link.text) {
+                                view.push_colored(e, ERROR, ERROR_BG);
+                            }
+                            window.request_redraw();
+                        } else if let Some(m) = view.message_at_row(&screen, row).filter(|m| !m.sender.is_empty()) {
+                            overlay = Overlay::UserCard(UserCard::new(m, history.as_ref()));
+                            window.request_redraw();
+                        }
+                    },
                     WindowEvent::Resized(physical_size) => {
                         screen.resize(*physical_size);
                         window.request_redraw();
@@ -81,7 +928,80 @@ fn main() {
                 }
             },
             Event::RedrawRequested(_) => {
-                println!("redraw");
+                // What moved since the last frame: a flick coasting to a stop and new lines
+                // sliding in
+                let now = Instant::now();
+                if let Some(pixels) = kinetic.step(now) {
+                    view.scroll_pixels(pixels, &screen);
+                }
+                view.animate(now.duration_since(last_frame).min(FRAME * 2).as_secs_f32(), &screen);
+                last_frame = now;
+                screen.clear();
+                match &overlay {
+                    Overlay::Results(results) => results.draw(&mut screen, &theme),
+                    Overlay::SearchPrompt(prompt) => {
+                        view.draw(&mut screen);
+                        prompt.draw(&mut screen, "search: ", &theme);
+                    },
+                    Overlay::Compose(prompt, completion, reply) => {
+                        view.draw(&mut screen);
+                        let label = format!("#{}> ", reply.as_ref().map_or(&channels[target], |m| &m.channel));
+                        prompt.draw(&mut screen, &label, &theme);
+                        let mut bottom = screen.rows().saturating_sub(prompt.rows(&screen, &label));
+                        if let Some(m) = reply {
+                            // What we're answering sits just above the input
+                            bottom = bottom.saturating_sub(1);
+                            let text = format!("Replying to {}: {}", m.display_name(), m.message);
+                            let text: String = text.chars().take(screen.cols().saturating_sub(2) as usize).collect();
+                            screen.fill_row(bottom, theme.header());
+                            screen.print_colored(bottom, 1, &text, theme.text(), theme.header());
+                        }
+                        if let Some(spell) = spell.as_ref().filter(|_| spell_on) {
+                            let misspelled = spell.misspelled(prompt.text(), Some(prompt.cursor()));
+                            prompt.squiggle(&mut screen, &label, &misspelled, MISSPELLED);
+                        }
+                        if let Some(completion) = completion {
+                            completion.draw(&mut screen, bottom, &theme);
+                        }
+                    },
+                    Overlay::SelectPrompt(prompt) => {
+                        view.draw(&mut screen);
+                        prompt.draw(&mut screen, "select regex: ", &theme);
+                    },
+                    Overlay::Find(find) => {
+                        view.draw(&mut screen);
+                        find.draw(&mut screen, &view, &theme);
+                    },
+                    Overlay::CommandLine(prompt, completion, error) => {
+                        view.draw(&mut screen);
+                        prompt.draw(&mut screen, ":", &theme);
+                        let mut bottom = screen.rows().saturating_sub(prompt.rows(&screen, ":"));
+                        if let Some(error) = error {
+                            bottom = bottom.saturating_sub(1);
+                            let text: String = error.chars().take(screen.cols().saturating_sub(2) as usize).collect();
+                            screen.fill_row(bottom, ERROR_BG);
+                            screen.print_colored(bottom, 1, &text, ERROR, ERROR_BG);
+                        }
+                        if let Some(completion) = completion {
+                            completion.draw(&mut screen, bottom, &theme);
+                        }
+                    },
+                    Overlay::BulkPreview(preview) => preview.draw(&mut screen, &theme),
+                    Overlay::UserCard(card) => {
+                        view.draw(&mut screen);
+                        card.draw(&mut screen, &theme);
+                    },
+                    Overlay::Chatters(panel) => panel.draw(&mut screen, &channels[target], &chatters[&channels[target]], &theme),
+                    Overlay::Stats(stats_view) => stats_view.draw(&mut screen, &stats, &theme),
+                    Overlay::Hints(mode) => {
+                        view.draw(&mut screen);
+                        mode.draw(&mut screen, &theme);
+                    },
+                    Overlay::None => view.draw(&mut screen),
+                }
+                if let Some(mode @ (Mode::Normal | Mode::Visual)) = key_mode(&overlay, &view) {
+                    keymap.draw_status(&mut screen, mode, &theme);
+                }
                 screen.update();
                 match screen.render() {
                     Ok(_) => {}
@@ -96,11 +1016,111 @@ fn main() {
                 start: _t,
                 requested_resume: _r,
             }) => {
-                let mut any = false;
+                filter.reload_if_changed();
+
+                // The dashboard moves with the clock even when chat is quiet
+                let mut any = matches!(overlay, Overlay::Stats(_));
+                any |= kinetic.is_moving() || view.is_animating();
+                any |= view.expire_pins(Instant::now());
+                any |= echo.expire(Instant::now(), &mut view, &template);
+                if mod_queue.poll(Instant::now(), &outbound) > 0 && mod_queue.len() == 0 {
+                    view.push("Finished sending moderation commands".to_string(), SUCCESS);
+                    any = true;
+                }
+
                 // Drain the ring buffer
-                while let Some(v) = cons.pop() {
-                    screen.print_string(l, 1, v);
-                    l += 1;
+                while let Some(event) = cons.pop() {
+                    let m = match event {
+                        ChatEvent::Message(m) => m,
+                        // Our own messages are only recorded once Twitch has accepted them
+                        ChatEvent::UserState { channel, tags } => {
+                            if let Some(m) = echo.confirm(&channel, tags, &mut view, &template) {
+                                if let Some(w) = &history_writer {
+                                    w.record(&m);
+                                }
+                                if let Some(logger) = &mut chat_logger {
+                                    logger.log(&m);
+                                }
+                                any = true;
+                            }
+                            continue;
+                        },
+                        ChatEvent::Membership { channel, change } => {
+                            if let Some(c) = chatters.get_mut(&channel) {
+                                c.apply(change, Instant::now());
+                            }
+                            any |= matches!(overlay, Overlay::Chatters(_));
+                            continue;
+                        },
+                    };
+                    if let Some(c) = chatters.get_mut(&m.channel) {
+                        c.saw_message(&m, Instant::now());
+                    }
+                    stats.record(&m);
+                    completer.saw_message(&m);
+                    if let Some(spell) = &mut spell {
+                        spell.saw_message(&m);
+                    }
+                    let newcomer = newcomers.check(&m);
+                    let special = Special::detect(&m, &rewards);
+                    if let Some(w) = &history_writer {
+                        w.record(&m);
+                    }
+                    if let Some(logger) = &mut chat_logger {
+                        logger.log(&m);
+                    }
+                    match (filter.apply(&m), special, newcomer) {
+                        (Some(Action::Hide), ..) => continue,
+                        (Some(Action::Dim), ..) => {
+                            let line = template.render(&m);
+                            view.push_message(m, line, DIM);
+                        },
+                        (Some(Action::Collapse), ..) => {
+                            let line = StyledLine::plain(format!("{}: …", m.sender));
+                            view.push_message(m, line, DIM);
+                        },
+                        // Rejections of what we sent, e.g. slow mode or a duplicate, go on the message
+                        (None, ..) if m.kind == MessageKind::Notice
+                            && m.tag("msg-id").is_some_and(|id| id.starts_with("msg_"))
+                            && echo.reject(&m.channel, &m.message, &mut view, &template) => {},
+                        (None, ..) if m.kind == MessageKind::Notice => {
+                            let line = template.render(&m).text;
+                            match m.tag("msg-id").map(moderation::outcome) {
+                                Some(Outcome::Failure) => view.push_colored(line, ERROR, ERROR_BG),
+                                Some(Outcome::Success) => view.push(line, SUCCESS),
+                                _ => view.push(line, NORMAL),
+                            };
+                        },
+                        // Paid messages get a header line saying what was paid
+                        (None, Some(special), _) => {
+                            let line = template.render(&m);
+                            if let Some(duration) = special.pinned_for().filter(|_| pin_paid) {
+                                view.pin(format!("{}  {}", special.header(), line.text), special.background(), duration);
+                            }
+                            view.push_colored(special.header(), SPECIAL_FG, special.background());
+                            view.push_message_colored(m, line, NORMAL, special.background());
+                        },
+                        // Greetings stand out and never get folded into a spam run
+                        (None, None, Some(newcomer)) => {
+                            let mut line = template.render(&m);
+                            line.prepend(&format!("{} ", newcomer.marker()));
+                            view.push_message_colored(m, line, NORMAL, newcomer.background());
+                        },
+                        (None, None, None) => {
+                            let now = Instant::now();
+                            match spam.check(&m.channel, &m.message, now) {
+                                Some((id, line)) => view.fold(id, line, m),
+                                None => {
+                                    let line = template.render(&m);
+                                    let channel = m.channel.clone();
+                                    let text = m.message.clone();
+                                    let plain = line.text.clone();
+                                    let id = view.push_message(m, line, NORMAL);
+                                    spam.record(&channel, &text, plain, id, now);
+                                },
+                            }
+                        },
+                    }
                     any = true;
                     //println!("Message: {}", v);
                 }
@@ -0,0 +1,230 @@
+// Moderator slash commands typed into the input line, and classification of the NOTICEs Twitch
+// answers them with.
+
+/// Seconds a timeout lasts when no duration is given.
+pub const DEFAULT_TIMEOUT: u32 = 600;
+// Twitch caps timeouts at two weeks
+const MAX_TIMEOUT: u32 = 1_209_600;
+const DEFAULT_SLOW: u32 = 30;
+
+/// Every command `Command::parse` understands, for completion.
+pub const NAMES: &[&str] = &[
+    "timeout", "ban", "unban", "untimeout", "delete", "clear", "slow", "slowoff", "emoteonly", "emoteonlyoff",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Timeout { user: String, seconds: u32, reason: String },
+    Ban { user: String, reason: String },
+    Unban { user: String },
+    Delete { id: String },
+    Clear,
+    Slow { seconds: u32 },
+    SlowOff,
+    EmoteOnly,
+    EmoteOnlyOff,
+}
+
+impl Command {
+    /// Parses a slash command. Returns None when the line isn't a moderation command at all, so it
+    /// can be sent as ordinary chat, and an error with usage when it is one but is malformed.
+    pub fn parse(line: &str) -> Option<Result<Command, String>> {
+        let line = line.trim();
+        let rest = line.strip_prefix('/')?;
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let mut words = args.split_whitespace();
+
+        let user = |w: Option<&str>| w.map(|u| u.trim_start_matches('@').to_lowercase());
+        let reason = |w: std::str::SplitWhitespace| w.collect::<Vec<_>>().join(" ");
+
+        let command = match name {
+            "timeout" => {
+                let user = match user(words.next()) {
+                    Some(u) => u,
+                    None => return Some(Err("usage: /timeout <user> [seconds] [reason]".to_string())),
+                };
+                let mut seconds = DEFAULT_TIMEOUT;
+                let mut rest: Vec<&str> = words.collect();
+                if let Some(first) = rest.first() {
+                    if let Some(s) = parse_duration(first) {
+                        if s == 0 || s > MAX_TIMEOUT {
+                            return Some(Err(format!("timeouts must be between 1 and {} seconds", MAX_TIMEOUT)));
+                        }
+                        seconds = s;
+                        rest.remove(0);
+                    }
+                }
+                Command::Timeout { user, seconds, reason: rest.join(" ") }
+            },
+            "ban" => match user(words.next()) {
+                Some(user) => Command::Ban { user, reason: reason(words) },
+                None => return Some(Err("usage: /ban <user> [reason]".to_string())),
+            },
+            "unban" | "untimeout" => match user(words.next()) {
+                Some(user) => Command::Unban { user },
+                None => return Some(Err(format!("usage: /{} <user>", name))),
+            },
+            "delete" => match words.next() {
+                Some(id) => Command::Delete { id: id.to_string() },
+                None => return Some(Err("usage: /delete <message id>".to_string())),
+            },
+            "clear" => Command::Clear,
+            "slow" => match words.next() {
+                None => Command::Slow { seconds: DEFAULT_SLOW },
+                Some(s) => match parse_duration(s) {
+                    Some(seconds) if seconds > 0 => Command::Slow { seconds },
+                    _ => return Some(Err("usage: /slow [seconds]".to_string())),
+                },
+            },
+            "slowoff" => Command::SlowOff,
+            "emoteonly" => Command::EmoteOnly,
+            "emoteonlyoff" => Command::EmoteOnlyOff,
+            _ => return None,
+        };
+        Some(Ok(command))
+    }
+
+    /// The IRC line that performs this command in `channel`.
+    pub fn to_irc(&self, channel: &str) -> String {
+        let command = match self {
+            Command::Timeout { user, seconds, reason } => format!("/timeout {} {} {}", user, seconds, reason),
+            Command::Ban { user, reason } => format!("/ban {} {}", user, reason),
+            Command::Unban { user } => format!("/unban {}", user),
+            Command::Delete { id } => format!("/delete {}", id),
+            Command::Clear => "/clear".to_string(),
+            Command::Slow { seconds } => format!("/slow {}", seconds),
+            Command::SlowOff => "/slowoff".to_string(),
+            Command::EmoteOnly => "/emoteonly".to_string(),
+            Command::EmoteOnlyOff => "/emoteonlyoff".to_string(),
+        };
+        format!("PRIVMSG #{} :{}", channel, command.trim_end())
+    }
+}
+
+/// Accepts plain seconds or a number with an s/m/h/d/w suffix, e.g. `10m`.
+fn parse_duration(s: &str) -> Option<u32> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let n: u32 = number.parse().ok()?;
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    n.checked_mul(scale)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    Info,
+}
+
+/// Sorts a NOTICE `msg-id` into whether the thing we asked for worked.
+pub fn outcome(msg_id: &str) -> Outcome {
+    match msg_id {
+        "no_permission" | "already_banned" | "unrecognized_cmd" | "invalid_user" => Outcome::Failure,
+        "slow_on" | "slow_off" | "emote_only_on" | "emote_only_off" => Outcome::Success,
+        id if id.ends_with("_success") => Outcome::Success,
+        id if id.starts_with("bad_") || id.starts_with("usage_") || id.starts_with("msg_") => Outcome::Failure,
+        _ => Outcome::Info,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn irc(line: &str) -> String {
+        Command::parse(line).unwrap().unwrap().to_irc("chan")
+    }
+
+    fn error(line: &str) -> String {
+        Command::parse(line).unwrap().unwrap_err()
+    }
+
+    #[test]
+    fn chat_isnt_a_command() {
+        assert_eq!(Command::parse("hello"), None);
+        assert_eq!(Command::parse("/me waves"), None);
+        assert_eq!(Command::parse("/timeoutx user"), None);
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_eq!(irc("/timeout @SomeUser"), "PRIVMSG #chan :/timeout someuser 600");
+        assert_eq!(irc("/timeout someuser 10m stop that"), "PRIVMSG #chan :/timeout someuser 600 stop that");
+        assert_eq!(irc("  /timeout someuser 2h  "), "PRIVMSG #chan :/timeout someuser 7200");
+        assert_eq!(irc("/timeout someuser 1w"), "PRIVMSG #chan :/timeout someuser 604800");
+        // A reason that doesn't start with a duration is all reason
+        assert_eq!(irc("/timeout someuser spamming 5 links"), "PRIVMSG #chan :/timeout someuser 600 spamming 5 links");
+        assert_eq!(irc("/timeout someuser 5x"), "PRIVMSG #chan :/timeout someuser 600 5x");
+
+        assert_eq!(error("/timeout"), "usage: /timeout <user> [seconds] [reason]");
+        assert_eq!(error("/timeout someuser 0"), "timeouts must be between 1 and 1209600 seconds");
+        assert_eq!(error("/timeout someuser 3w"), "timeouts must be between 1 and 1209600 seconds");
+    }
+
+    #[test]
+    fn bans() {
+        assert_eq!(irc("/ban @SomeUser"), "PRIVMSG #chan :/ban someuser");
+        assert_eq!(irc("/ban someuser  repeated   slurs"), "PRIVMSG #chan :/ban someuser repeated slurs");
+        assert_eq!(irc("/unban SomeUser"), "PRIVMSG #chan :/unban someuser");
+        assert_eq!(irc("/untimeout someuser"), "PRIVMSG #chan :/unban someuser");
+        assert_eq!(error("/ban"), "usage: /ban <user> [reason]");
+        assert_eq!(error("/unban"), "usage: /unban <user>");
+        assert_eq!(error("/untimeout"), "usage: /untimeout <user>");
+    }
+
+    #[test]
+    fn channel_commands() {
+        assert_eq!(irc("/delete 1a2b-3c"), "PRIVMSG #chan :/delete 1a2b-3c");
+        assert_eq!(error("/delete"), "usage: /delete <message id>");
+        assert_eq!(irc("/clear"), "PRIVMSG #chan :/clear");
+        assert_eq!(irc("/slow"), "PRIVMSG #chan :/slow 30");
+        assert_eq!(irc("/slow 2m"), "PRIVMSG #chan :/slow 120");
+        assert_eq!(error("/slow 0"), "usage: /slow [seconds]");
+        assert_eq!(error("/slow often"), "usage: /slow [seconds]");
+        assert_eq!(irc("/slowoff"), "PRIVMSG #chan :/slowoff");
+        assert_eq!(irc("/emoteonly"), "PRIVMSG #chan :/emoteonly");
+        assert_eq!(irc("/emoteonlyoff"), "PRIVMSG #chan :/emoteonlyoff");
+    }
+
+    #[test]
+    fn every_name_parses() {
+        for name in NAMES {
+            assert!(Command::parse(&format!("/{} someone", name)).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("45"), Some(45));
+        assert_eq!(parse_duration("45s"), Some(45));
+        assert_eq!(parse_duration("3d"), Some(259_200));
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("10y"), None);
+        assert_eq!(parse_duration("99999999w"), None);
+    }
+
+    #[test]
+    fn notices_sort_into_outcomes() {
+        assert_eq!(outcome("timeout_success"), Outcome::Success);
+        assert_eq!(outcome("ban_success"), Outcome::Success);
+        assert_eq!(outcome("slow_on"), Outcome::Success);
+        assert_eq!(outcome("emote_only_off"), Outcome::Success);
+        assert_eq!(outcome("no_permission"), Outcome::Failure);
+        assert_eq!(outcome("already_banned"), Outcome::Failure);
+        assert_eq!(outcome("bad_timeout_mod"), Outcome::Failure);
+        assert_eq!(outcome("usage_ban"), Outcome::Failure);
+        assert_eq!(outcome("msg_ratelimit"), Outcome::Failure);
+        assert_eq!(outcome("host_on"), Outcome::Info);
+        assert_eq!(outcome(""), Outcome::Info);
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::env;
+use crate::chat::{self, ChatMessage, MessageKind};
+use crate::history::History;
+
+// Someone who hasn't talked for this long is greeted again, streams rarely run longer and rarely
+// start sooner after the last one ended
+const STREAM_GAP: i64 = 6 * 60 * 60 * 1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Newcomer {
+    /// Twitch says this is their first message in the channel ever.
+    First,
+    /// Twitch says they have been away and came back.
+    Returning,
+    /// Our own history has nothing from them this stream.
+    FirstThisStream,
+}
+
+impl Newcomer {
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Newcomer::First => "[first]",
+            Newcomer::Returning => "[returning]",
+            Newcomer::FirstThisStream => "[hello]",
+        }
+    }
+
+    pub fn background(&self) -> [f32; 3] {
+        match self {
+            Newcomer::First => [0.1, 0.3, 0.15],
+            Newcomer::Returning => [0.1, 0.2, 0.35],
+            Newcomer::FirstThisStream => [0.2, 0.2, 0.1],
+        }
+    }
+}
+
+/// Spots people worth greeting, from Twitch's tags or, when `DETECT_FIRST_MESSAGES` is set and
+/// the tags are missing, from whether our history has seen them this stream.
+pub struct Newcomers {
+    detect: bool,
+    // When each (channel, sender) last talked, read from the history once at startup and kept up
+    // from then on, so checking a message never waits on the database
+    last_seen: HashMap<(String, String), i64>,
+}
+
+impl Newcomers {
+    pub fn from_env(history: Option<&History>) -> Self {
+        let detect = env::var("DETECT_FIRST_MESSAGES").is_ok_and(|v| v == "1" || v == "true");
+        let mut newcomers = Self::new(detect);
+        if let (true, Some(history)) = (detect, history) {
+            match history.last_seen_since(chat::now_millis() - STREAM_GAP) {
+                Ok(seen) => newcomers.seed(seen),
+                Err(e) => println!("Couldn't read who has talked this stream: {}", e),
+            }
+        }
+        newcomers
+    }
+
+    fn new(detect: bool) -> Self {
+        Self {
+            detect,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    fn seed(&mut self, seen: Vec<(String, String, i64)>) {
+        self.last_seen.extend(seen.into_iter().map(|(channel, sender, ts)| ((channel, sender), ts)));
+    }
+
+    pub fn check(&mut self, m: &ChatMessage) -> Option<Newcomer> {
+        if m.sender.is_empty() || !matches!(m.kind, MessageKind::Chat | MessageKind::Action) {
+            return None;
+        }
+        let last_seen = self.last_seen.insert((m.channel.clone(), m.sender.clone()), m.timestamp);
+
+        if m.tag("first-msg") == Some("1") {
+            return Some(Newcomer::First);
+        }
+        if m.tag("returning-chatter") == Some("1") {
+            return Some(Newcomer::Returning);
+        }
+        // Sources that send the tags know better than we do
+        if !self.detect || m.tags.contains_key("first-msg") {
+            return None;
+        }
+        match last_seen {
+            Some(ts) if m.timestamp - ts < STREAM_GAP => None,
+            _ => Some(Newcomer::FirstThisStream),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(channel: &str, sender: &str, timestamp: i64, tags: &[(&str, &str)]) -> ChatMessage {
+        let tags = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut m = ChatMessage::sent(channel, sender, "hi", tags);
+        m.timestamp = timestamp;
+        m
+    }
+
+    #[test]
+    fn tags_win() {
+        let mut newcomers = Newcomers::new(true);
+        let tagged = [("first-msg", "1"), ("returning-chatter", "0")];
+        assert_eq!(newcomers.check(&chat("chan", "new", 0, &tagged)), Some(Newcomer::First));
+        let tagged = [("first-msg", "0"), ("returning-chatter", "1")];
+        assert_eq!(newcomers.check(&chat("chan", "back", 0, &tagged)), Some(Newcomer::Returning));
+        // Twitch says they aren't new, that beats our own guess
+        let tagged = [("first-msg", "0"), ("returning-chatter", "0")];
+        assert_eq!(newcomers.check(&chat("chan", "regular", 0, &tagged)), None);
+    }
+
+    #[test]
+    fn greets_once_a_stream() {
+        let mut newcomers = Newcomers::new(true);
+        newcomers.seed(vec![("chan".to_string(), "earlier".to_string(), 1_000)]);
+        assert_eq!(newcomers.check(&chat("chan", "earlier", 2_000, &[])), None);
+        assert_eq!(newcomers.check(&chat("chan", "someone", 2_000, &[])), Some(Newcomer::FirstThisStream));
+        assert_eq!(newcomers.check(&chat("chan", "someone", 3_000, &[])), None);
+        // Seen here doesn't count for another channel
+        assert_eq!(newcomers.check(&chat("other", "someone", 3_000, &[])), Some(Newcomer::FirstThisStream));
+        // Back after long enough for a new stream
+        assert_eq!(newcomers.check(&chat("chan", "someone", 3_000 + STREAM_GAP, &[])), Some(Newcomer::FirstThisStream));
+    }
+
+    #[test]
+    fn guessing_is_opt_in() {
+        let mut newcomers = Newcomers::new(false);
+        assert_eq!(newcomers.check(&chat("chan", "someone", 0, &[])), None);
+    }
+}
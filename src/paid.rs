@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use crate::chat::ChatMessage;
+
+// Twitch doesn't put reward names in chat, only their ids. Names can be given in a plain text
+// file, one reward per line:
+//
+//   # reward id                           name
+//   7d5c0f6a-4b51-4b9e-a1d6-4c5f4f4e1c2a  Hydrate!
+
+const PAID_BG: [f32; 3] = [0.35, 0.25, 0.0];
+const REWARD_BG: [f32; 3] = [0.15, 0.1, 0.3];
+const HIGHLIGHTED_BG: [f32; 3] = [0.3, 0.1, 0.25];
+
+/// A message someone paid for, with money or channel points.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Special {
+    Paid { amount: String, pinned_for: Duration },
+    Reward { name: String },
+    Highlighted,
+}
+
+impl Special {
+    pub fn detect(m: &ChatMessage, rewards: &Rewards) -> Option<Special> {
+        if let Some(amount) = m.tag("pinned-chat-paid-amount").and_then(|a| a.parse::<u64>().ok()) {
+            let exponent = m.tag("pinned-chat-paid-exponent").and_then(|e| e.parse().ok()).unwrap_or(2);
+            let currency = m.tag("pinned-chat-paid-currency").unwrap_or("");
+            return Some(Special::Paid {
+                amount: format_amount(amount, exponent, currency),
+                pinned_for: pin_duration(m.tag("pinned-chat-paid-level").unwrap_or("")),
+            });
+        }
+        if let Some(id) = m.tag("custom-reward-id") {
+            return Some(Special::Reward { name: rewards.name(id) });
+        }
+        if m.tag("msg-id") == Some("highlighted-message") {
+            return Some(Special::Highlighted);
+        }
+        None
+    }
+
+    pub fn header(&self) -> String {
+        match self {
+            Special::Paid { amount, .. } => format!("Hype Chat {}", amount),
+            Special::Reward { name } => format!("Redeemed {}", name),
+            Special::Highlighted => "Highlighted message".to_string(),
+        }
+    }
+
+    pub fn background(&self) -> [f32; 3] {
+        match self {
+            Special::Paid { .. } => PAID_BG,
+            Special::Reward { .. } => REWARD_BG,
+            Special::Highlighted => HIGHLIGHTED_BG,
+        }
+    }
+
+    /// How long Twitch keeps the message pinned, None for things that don't get pinned.
+    pub fn pinned_for(&self) -> Option<Duration> {
+        match self {
+            Special::Paid { pinned_for, .. } => Some(*pinned_for),
+            _ => None,
+        }
+    }
+}
+
+/// Amounts come in the currency's minor unit with the exponent to scale by, e.g. 500 and 2 for
+/// 5.00.
+fn format_amount(amount: u64, exponent: u32, currency: &str) -> String {
+    let scale = 10u64.pow(exponent);
+    let value = if exponent == 0 {
+        amount.to_string()
+    } else {
+        format!("{}.{:0width$}", amount / scale, amount % scale, width = exponent as usize)
+    };
+    format!("{} {}", value, currency).trim_end().to_string()
+}
+
+/// The pin time for each Hype Chat level, as Twitch documents them.
+fn pin_duration(level: &str) -> Duration {
+    let seconds = match level {
+        "ONE" => 30,
+        "TWO" => 150,
+        "THREE" => 5 * 60,
+        "FOUR" => 10 * 60,
+        "FIVE" => 30 * 60,
+        "SIX" => 60 * 60,
+        "SEVEN" => 2 * 60 * 60,
+        "EIGHT" => 3 * 60 * 60,
+        "NINE" => 4 * 60 * 60,
+        "TEN" => 5 * 60 * 60,
+        _ => 30,
+    };
+    Duration::from_secs(seconds)
+}
+
+pub struct Rewards {
+    names: HashMap<String, String>,
+}
+
+impl Rewards {
+    pub fn load(path: &Path) -> Self {
+        let names = fs::read_to_string(path).unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| l.split_once(char::is_whitespace))
+            .map(|(id, name)| (id.to_string(), name.trim().to_string()))
+            .collect();
+        Self { names }
+    }
+
+    fn name(&self, id: &str) -> String {
+        self.names.get(id).cloned().unwrap_or_else(|| "a channel points reward".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(tags: &[(&str, &str)]) -> ChatMessage {
+        let tags = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        ChatMessage::sent("chan", "someone", "hi", tags)
+    }
+
+    fn rewards() -> Rewards {
+        Rewards { names: [("abc-123".to_string(), "Hydrate!".to_string())].into_iter().collect() }
+    }
+
+    #[test]
+    fn amounts_scale_by_their_exponent() {
+        assert_eq!(format_amount(500, 2, "USD"), "5.00 USD");
+        assert_eq!(format_amount(1999, 2, "EUR"), "19.99 EUR");
+        assert_eq!(format_amount(5, 2, "USD"), "0.05 USD");
+        assert_eq!(format_amount(1000, 0, "JPY"), "1000 JPY");
+        assert_eq!(format_amount(12345, 3, "KWD"), "12.345 KWD");
+        assert_eq!(format_amount(250, 2, ""), "2.50");
+    }
+
+    #[test]
+    fn levels_pin_for_documented_times() {
+        assert_eq!(pin_duration("ONE"), Duration::from_secs(30));
+        assert_eq!(pin_duration("TWO"), Duration::from_secs(150));
+        assert_eq!(pin_duration("TEN"), Duration::from_secs(5 * 60 * 60));
+        assert_eq!(pin_duration("ELEVEN"), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn hype_chats() {
+        let m = message(&[
+            ("pinned-chat-paid-amount", "1500"),
+            ("pinned-chat-paid-exponent", "2"),
+            ("pinned-chat-paid-currency", "GBP"),
+            ("pinned-chat-paid-level", "THREE"),
+        ]);
+        let special = Special::detect(&m, &rewards()).unwrap();
+        assert_eq!(special, Special::Paid { amount: "15.00 GBP".to_string(), pinned_for: Duration::from_secs(300) });
+        assert_eq!(special.header(), "Hype Chat 15.00 GBP");
+        assert_eq!(special.pinned_for(), Some(Duration::from_secs(300)));
+
+        // Twitch's usual exponent when it's missing
+        let m = message(&[("pinned-chat-paid-amount", "100")]);
+        assert_eq!(Special::detect(&m, &rewards()).unwrap().header(), "Hype Chat 1.00");
+        let m = message(&[("pinned-chat-paid-amount", "lots")]);
+        assert_eq!(Special::detect(&m, &rewards()), None);
+    }
+
+    #[test]
+    fn rewards_and_highlights() {
+        let m = message(&[("custom-reward-id", "abc-123")]);
+        assert_eq!(Special::detect(&m, &rewards()).unwrap().header(), "Redeemed Hydrate!");
+        let m = message(&[("custom-reward-id", "unknown")]);
+        assert_eq!(Special::detect(&m, &rewards()).unwrap().header(), "Redeemed a channel points reward");
+
+        let m = message(&[("msg-id", "highlighted-message")]);
+        let special = Special::detect(&m, &rewards()).unwrap();
+        assert_eq!(special, Special::Highlighted);
+        assert_eq!(special.pinned_for(), None);
+
+        assert_eq!(Special::detect(&message(&[("msg-id", "")]), &rewards()), None);
+    }
+}
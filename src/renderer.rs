@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use winit::window::Window;
 use crossfont::{self, FontDesc, Style, Slant, Weight, Size, GlyphKey};
 use wgpu::util::DeviceExt;
@@ -34,6 +35,7 @@ pub struct ProjectionUniform {
     cell_dim: [f32; 2],
     size: [f32; 2],
     offset: [f32; 2],
+    clip: [f32; 2],
 }
 
 const VERTICES: &[Vertex] = &[
@@ -55,6 +57,11 @@ const VERTICES: &[Vertex] = &[
     },
 ];
 
+const FONT_FAMILY: &str = "SF Mono";
+
+// Room for this many pixel placed rects a frame, anything past it is dropped
+const MAX_RECTS: usize = 4096;
+
 // Makes two counterclockwise triangles out of the four points
 const INDICES: &[u16] = &[0,2,1,2,0,3];
 
@@ -67,6 +74,8 @@ pub struct InstanceRaw {
     bg_color: [f32;3],
     fg_color: [f32;4],
     position: [f32;4],
+    // 1.0 when moved by the scroll offset
+    scrolls: f32,
 }
 
 impl InstanceRaw {
@@ -106,16 +115,30 @@ impl InstanceRaw {
                     shader_location: 10,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32;17]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
 }
 
+/// How text is drawn besides its colours.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
 pub struct Cell {
     col: u32,
     row: u32,
     bg_color: [f32;3],
     fg_color: [f32;4],
+    scrolls: bool,
     glyph: Glyph,
 }
 
@@ -129,19 +152,31 @@ impl Cell {
             bg_color: self.bg_color,
             fg_color: self.fg_color,
             position: [self.glyph.left, self.glyph.top, self.glyph.width, self.glyph.height],
+            scrolls: if self.scrolls { 1.0 } else { 0.0 },
         }
     }
 }
 
 pub struct Screen {
-    offset_x: u32,
-    offset_y: u32,
+    offset_x: f32,
+    offset_y: f32,
+    // Pixel rows scrolling cells are cut off outside of
+    clip_top: f32,
+    clip_bottom: f32,
+    // Whether cells drawn now move with the offset
+    scrolling: bool,
     cell_width: f32,
     cell_height: f32,
     cells: Vec<Cell>,
+    // Where each occupied (col, row) is in `cells`, so drawing over a cell replaces it
+    cell_index: HashMap<(u32, u32), usize>,
+    rects: Vec<InstanceRaw>,
 
     font_key: crossfont::FontKey,
+    // Bold, italic and bold italic faces, falling back to the regular one when missing
+    styled_font_keys: [crossfont::FontKey; 3],
     font_size: f32,
+    clear_color: [f32; 3],
 
     atlas: Atlas,
 
@@ -152,10 +187,12 @@ pub struct Screen {
     size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: wgpu::RenderPipeline,
     bg_render_pipeline: wgpu::RenderPipeline,
+    rect_render_pipeline: wgpu::RenderPipeline,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_indices: u32,
     instance_buffer: wgpu::Buffer,
+    rect_buffer: wgpu::Buffer,
     projection_buffer: wgpu::Buffer,
     projection_bind_group: wgpu::BindGroup,
     diffuse_bind_group: wgpu::BindGroup,
@@ -217,13 +254,22 @@ impl Screen {
 
         let font_size = 20.0;
         let font_desc = FontDesc::new::<String>(
-            "SF Mono".into(),
+            FONT_FAMILY.into(),
             Style::Description{
                 slant: Slant::Normal,
                 weight: Weight::Normal,
             });
 
         let (regular, metrics) = atlas.load_font(&font_desc, font_size);
+        let variant = |atlas: &mut Atlas, slant, weight| {
+            let desc = FontDesc::new::<String>(FONT_FAMILY.into(), Style::Description { slant, weight });
+            atlas.load_variant(&desc, font_size).unwrap_or(regular)
+        };
+        let styled_font_keys = [
+            variant(&mut atlas, Slant::Normal, Weight::Bold),
+            variant(&mut atlas, Slant::Italic, Weight::Normal),
+            variant(&mut atlas, Slant::Italic, Weight::Bold),
+        ];
         println!("Average Advance: {}", metrics.average_advance);
         println!("Line Height    : {}", metrics.line_height);
         println!("Descent        : {}", metrics.descent);
@@ -238,66 +284,7 @@ impl Screen {
         let cell_width = metrics.average_advance;
         let cell_height = metrics.line_height;
 
-        let middle_cell = Cell {
-            col: 1,
-            row: 1,
-            bg_color: [0.0,0.0,0.0],
-            fg_color: [1.0,0.0,0.0,1.0],
-            glyph: atlas.get_glyph(&device, &queue, GlyphKey {
-                character: 'b',
-                font_key: regular,
-                size: Size::new(20.0),
-            }).unwrap(),
-        };
-
-        println!("Middle Cell: {:?}", middle_cell.to_instance());
-
-        let mut cells = Vec::new();
-        cells.push(Cell {
-            col: 1,
-            row: 0,
-            bg_color: [0.0,0.0,0.0],
-            fg_color: [1.0,1.0,1.0,1.0],
-            glyph: atlas.get_glyph(&device, &queue, GlyphKey {
-                character: 'u',
-                font_key: regular,
-                size: Size::new(20.0),
-            }).unwrap(),
-        });
-        cells.push(Cell {
-            col: 0,
-            row: 1,
-            bg_color: [0.0,0.0,0.0],
-            fg_color: [1.0,1.0,1.0,0.5],
-            glyph: atlas.get_glyph(&device, &queue, GlyphKey {
-                character: 'a',
-                font_key: regular,
-                size: Size::new(20.0),
-            }).unwrap(),
-        });
-        cells.push(middle_cell);
-        cells.push(Cell {
-            col: 2,
-            row: 1,
-            bg_color: [0.0,0.0,0.0],
-            fg_color: [1.0,1.0,1.0,0.5],
-            glyph: atlas.get_glyph(&device, &queue, GlyphKey {
-                character: 'c',
-                font_key: regular,
-                size: Size::new(20.0),
-            }).unwrap(),
-        });
-        cells.push(Cell {
-            col: 1,
-            row: 2,
-            bg_color: [0.0,0.0,0.0],
-            fg_color: [1.0,1.0,1.0,0.5],
-            glyph: atlas.get_glyph(&device, &queue, GlyphKey {
-                character: 'd',
-                font_key: regular,
-                size: Size::new(20.0),
-            }).unwrap(),
-        });
+        let cells = Vec::new();
 
         let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Instance Buffer"),
@@ -306,6 +293,13 @@ impl Screen {
             mapped_at_creation: false,
         });
 
+        let rect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Rect Buffer"),
+            size: (MAX_RECTS * std::mem::size_of::<InstanceRaw>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let texture_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -349,6 +343,7 @@ impl Screen {
                         cell_dim: [cell_width as f32, cell_height as f32],
                         size: [size.width as f32, size.height as f32],
                         offset: [0.0, 0.0],
+                        clip: [0.0, size.height as f32],
                     };
 
         // Projection Uniform needs the metrics from the font (we should not have this as a
@@ -366,7 +361,8 @@ impl Screen {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        // Fragments read the clip rows
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer{
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -436,6 +432,44 @@ impl Screen {
             multiview: None,
         });
 
+        let rect_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Rect Render Piepline"),
+            layout: Some(&bg_render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_rect",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_bg",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent::REPLACE,
+                        alpha: wgpu::BlendComponent::REPLACE,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
@@ -486,15 +520,22 @@ impl Screen {
         });
 
         Self {
-            offset_x: 0,
-            offset_y: 0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            clip_top: 0.0,
+            clip_bottom: f32::MAX,
+            scrolling: false,
             cell_width: cell_width as f32,
             cell_height: cell_height as f32,
             size,
 
             font_key: regular,
+            styled_font_keys,
             font_size,
+            clear_color: [0.1, 0.1, 0.1],
             cells,
+            cell_index: HashMap::new(),
+            rects: Vec::new(),
 
             surface,
             device,
@@ -502,10 +543,12 @@ impl Screen {
             config,
             render_pipeline,
             bg_render_pipeline,
+            rect_render_pipeline,
             vertex_buffer,
             index_buffer,
             atlas,
             instance_buffer,
+            rect_buffer,
             num_indices,
             projection_buffer,
             projection_bind_group,
@@ -519,21 +562,56 @@ impl Screen {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-
-            self.queue.write_buffer(
-                &self.projection_buffer,
-                0,
-                bytemuck::cast_slice(&[
-                    ProjectionUniform {
-                        cell_dim: [self.cell_width, self.cell_height],
-                        size: [new_size.width as f32, new_size.height as f32],
-                        offset: [self.offset_x as f32, self.offset_y as f32],
-                    },
-                ]),
-                );
+            self.write_projection();
         }
     }
 
+    fn write_projection(&self) {
+        self.queue.write_buffer(
+            &self.projection_buffer,
+            0,
+            bytemuck::cast_slice(&[
+                ProjectionUniform {
+                    cell_dim: [self.cell_width, self.cell_height],
+                    size: [self.size.width as f32, self.size.height as f32],
+                    offset: [self.offset_x, self.offset_y],
+                    clip: [self.clip_top, self.clip_bottom],
+                },
+            ]),
+            );
+    }
+
+    pub fn font_size(&self) -> f32 {
+        self.font_size
+    }
+
+    /// Loads the font at another size. Cells change size with it, so everything has to be laid
+    /// out again for the next frame.
+    pub fn set_font_size(&mut self, size: f32) -> Result<(), String> {
+        let face = |slant, weight| FontDesc::new::<String>(FONT_FAMILY.into(), Style::Description { slant, weight });
+        let regular = self.atlas.load_variant(&face(Slant::Normal, Weight::Normal), size)
+            .ok_or_else(|| format!("Can't load {} at size {}", FONT_FAMILY, size))?;
+        let metrics = self.atlas.metrics(regular, size)
+            .ok_or_else(|| format!("No metrics for {} at size {}", FONT_FAMILY, size))?;
+        let mut variant = |slant, weight| self.atlas.load_variant(&face(slant, weight), size).unwrap_or(regular);
+        self.styled_font_keys = [
+            variant(Slant::Normal, Weight::Bold),
+            variant(Slant::Italic, Weight::Normal),
+            variant(Slant::Italic, Weight::Bold),
+        ];
+        self.font_key = regular;
+        self.font_size = size;
+        self.cell_width = metrics.average_advance as f32;
+        self.cell_height = metrics.line_height as f32;
+        self.write_projection();
+        Ok(())
+    }
+
+    /// The colour behind everything, where no cell is drawn.
+    pub fn set_clear_color(&mut self, color: [f32; 3]) {
+        self.clear_color = color;
+    }
+
     pub fn update(&mut self) {
         // TODO: Check if self.instance_data() size is larger than our buffer and realloc
         self.queue.write_buffer(
@@ -541,24 +619,133 @@ impl Screen {
             0,
             bytemuck::cast_slice(&self.instance_data()),
         );
+        self.queue.write_buffer(&self.rect_buffer, 0, bytemuck::cast_slice(&self.rects));
+    }
+
+    /// Number of whole cells that fit across the window.
+    pub fn cols(&self) -> u32 {
+        (self.size.width as f32 / self.cell_width) as u32
+    }
+
+    /// Number of whole rows that fit down the window.
+    pub fn rows(&self) -> u32 {
+        (self.size.height as f32 / self.cell_height) as u32
+    }
+
+    /// Drops every cell so the next frame can be laid out from scratch.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.cell_index.clear();
+        self.rects.clear();
+        self.scrolling = false;
+    }
+
+    /// Cells and rects drawn from here until `end_scroll` are moved up by `pixels`, for scrolling
+    /// by less than a row. They are cut off above `top`, the first row they are allowed to cover.
+    pub fn begin_scroll(&mut self, pixels: f32, top: u32) {
+        self.offset_y = -pixels;
+        self.clip_top = top as f32 * self.cell_height;
+        self.scrolling = true;
+        self.write_projection();
+    }
+
+    pub fn end_scroll(&mut self) {
+        self.scrolling = false;
+    }
+
+    /// Size of one cell in pixels, as (width, height).
+    pub fn cell_size(&self) -> (f32, f32) {
+        (self.cell_width, self.cell_height)
+    }
+
+    /// Fills a rectangle given in pixels. Rects are drawn over cell backgrounds but under glyphs,
+    /// for things that don't line up with the grid like graphs and underlines.
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 3]) {
+        if self.rects.len() >= MAX_RECTS {
+            return;
+        }
+        self.rects.push(InstanceRaw {
+            cell_coords: [0.0, 0.0],
+            tex_offset: [0.0, 0.0],
+            tex_size: [0.0, 0.0],
+            bg_color: color,
+            fg_color: [0.0, 0.0, 0.0, 0.0],
+            position: [x, y, width, height],
+            scrolls: if self.scrolling { 1.0 } else { 0.0 },
+        });
     }
 
-    pub fn print_string(&mut self, row: u32, col: u32, s: String) {
+    /// Draws a wavy line along the bottom of `len` cells, the way editors mark spelling mistakes.
+    pub fn squiggle(&mut self, row: u32, col: u32, len: usize, color: [f32; 3]) {
+        let start = col as f32 * self.cell_width;
+        let end = start + len as f32 * self.cell_width;
+        let y = (row + 1) as f32 * self.cell_height - 3.0;
+        let step = (self.cell_width / 4.0).max(2.0);
+        let mut x = start;
+        let mut up = false;
+        while x < end {
+            self.rect(x, if up { y - 1.0 } else { y }, step.min(end - x), 1.0, color);
+            x += step;
+            up = !up;
+        }
+    }
+
+    pub fn print_colored(&mut self, row: u32, col: u32, s: &str, fg_color: [f32; 4], bg_color: [f32; 3]) {
+        self.print_styled(row, col, s, fg_color, bg_color, TextStyle::default());
+    }
+
+    pub fn print_styled(&mut self, row: u32, col: u32, s: &str, fg_color: [f32; 4], bg_color: [f32; 3], style: TextStyle) {
+        let font_key = match (style.bold, style.italic) {
+            (false, false) => self.font_key,
+            (true, false) => self.styled_font_keys[0],
+            (false, true) => self.styled_font_keys[1],
+            (true, true) => self.styled_font_keys[2],
+        };
+        if style.underline {
+            let width = s.chars().count() as f32 * self.cell_width;
+            let y = (row + 1) as f32 * self.cell_height - 2.0;
+            let color = [fg_color[0], fg_color[1], fg_color[2]];
+            self.rect(col as f32 * self.cell_width, y, width, 1.0, color);
+        }
         for (i, c) in s.chars().enumerate() {
-            self.cells.push(Cell {
+            let cell = Cell {
                 col: col + i as u32,
                 row,
-                bg_color: [0.0, 0.0, 0.0],
-                fg_color: [1.0, 1.0, 1.0, 1.0],
+                bg_color,
+                fg_color,
+                scrolls: self.scrolling,
                 glyph: self.atlas.get_glyph(&self.device, &self.queue, GlyphKey {
                     character: c,
-                    font_key: self.font_key,
+                    font_key,
                     size: Size::new(self.font_size),
                 }).unwrap(),
-            });
+            };
+            match self.cell_index.get(&(cell.col, row)) {
+                Some(existing) => self.cells[*existing] = cell,
+                None => {
+                    self.cell_index.insert((cell.col, row), self.cells.len());
+                    self.cells.push(cell);
+                },
+            }
         }
     }
 
+    /// Paints the background of a whole row, e.g. for a header or prompt bar.
+    pub fn fill_row(&mut self, row: u32, bg_color: [f32; 3]) {
+        self.fill(row, 0, self.cols(), bg_color);
+    }
+
+    /// Paints the background of `width` cells starting at `col`.
+    pub fn fill(&mut self, row: u32, col: u32, width: u32, bg_color: [f32; 3]) {
+        let blank = " ".repeat(width as usize);
+        self.print_colored(row, col, &blank, [0.0, 0.0, 0.0, 0.0], bg_color);
+    }
+
+    /// The cell under a point in window coordinates, as (col, row).
+    pub fn cell_at(&self, x: f64, y: f64) -> (u32, u32) {
+        ((x.max(0.0) / self.cell_width as f64) as u32, (y.max(0.0) / self.cell_height as f64) as u32)
+    }
+
     fn instance_data(&self) -> Vec<InstanceRaw> {
         self.cells.iter().map(Cell::to_instance).collect::<Vec<_>>()
     }
@@ -579,9 +766,9 @@ impl Screen {
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
+                            r: self.clear_color[0] as f64,
+                            g: self.clear_color[1] as f64,
+                            b: self.clear_color[2] as f64,
                             a: 1.0,
                         }),
                         store: true,
@@ -597,6 +784,13 @@ impl Screen {
             bg_render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             bg_render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
             bg_render_pass.draw_indexed(0..self.num_indices, 0, 0..self.cells.len() as _);
+
+            // Then anything placed by pixel on top of them
+            if !self.rects.is_empty() {
+                bg_render_pass.set_pipeline(&self.rect_render_pipeline);
+                bg_render_pass.set_vertex_buffer(1, self.rect_buffer.slice(..));
+                bg_render_pass.draw_indexed(0..self.num_indices, 0, 0..self.rects.len() as _);
+            }
         }
 
         {
@@ -55,7 +55,17 @@ impl Atlas {
 
         let metrics =  self.rasterizer.metrics(regular, font_size).unwrap();
         self.row_height = metrics.line_height as u32;
-        return (regular, metrics);
+        (regular, metrics)
+    }
+
+    /// Loads another face of the font, e.g. bold, without touching the metrics the grid is
+    /// laid out with. None when the system has nothing suitable.
+    pub fn load_variant(&mut self, font: &FontDesc, size: f32) -> Option<FontKey> {
+        self.rasterizer.load_font(font, Size::new(size)).ok()
+    }
+
+    pub fn metrics(&self, font: FontKey, size: f32) -> Option<Metrics> {
+        self.rasterizer.metrics(font, Size::new(size)).ok()
     }
 
     pub fn texture_view(&mut self, device: &Device) -> wgpu::TextureView {
@@ -77,11 +87,8 @@ impl Atlas {
     }
 
     pub fn get_glyph(&mut self, device: &Device, queue: &Queue, key: GlyphKey) -> Option<Glyph> {
-        if self.glyphs.contains_key(&key) {
-            return match self.glyphs.get(&key) {
-                Some(g) => Some(g.clone()),
-                None => None,
-            };
+        if let Some(g) = self.glyphs.get(&key) {
+            return Some(g.clone());
         }
 
         let rast_glyph = self.rasterizer.get_glyph(key).unwrap();
@@ -119,7 +126,7 @@ impl Atlas {
 
         queue.write_texture(
             wgpu::ImageCopyTexture {
-                texture: &texture,
+                texture,
                 mip_level: 0,
                 origin: wgpu::Origin3d{
                     x: target_x, // TODO: Offset in the atlas
@@ -154,10 +161,7 @@ impl Atlas {
 
         self.glyphs.insert(key, g);
 
-        return match self.glyphs.get(&key) {
-            Some(g) => Some(g.clone()),
-            None => None,
-        };
+        self.glyphs.get(&key).cloned()
     }
 
     // location_for returns the next x/y in the atlas to store a texture of the given size
@@ -177,7 +181,7 @@ impl Atlas {
             self.h_offset += width;
             return (x, self.v_offset);
         }
-        return (self.h_offset, self.v_offset);
+        (self.h_offset, self.v_offset)
     }
 
     #[allow(dead_code)]
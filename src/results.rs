@@ -0,0 +1,80 @@
+use chrono::{Local, TimeZone};
+use crate::history::Hit;
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+const MATCH_FG: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const MATCH_BG: [f32; 3] = [0.3, 0.25, 0.0];
+
+/// Formats a millisecond unix timestamp in local time.
+pub fn local_time(timestamp: i64, format: &str) -> String {
+    match Local.timestamp_millis_opt(timestamp).single() {
+        Some(t) => t.format(format).to_string(),
+        None => "?".to_string(),
+    }
+}
+
+/// Full window view listing history search hits, newest first, with the matched words
+/// highlighted.
+pub struct ResultsView {
+    query: String,
+    hits: Vec<Hit>,
+    // Index of the first hit shown
+    scroll: usize,
+}
+
+impl ResultsView {
+    pub fn new(query: String, hits: Vec<Hit>) -> Self {
+        Self {
+            query,
+            hits,
+            scroll: 0,
+        }
+    }
+
+    pub fn scroll_by(&mut self, delta: i32) {
+        let max = self.hits.len().saturating_sub(1) as i64;
+        self.scroll = (self.scroll as i64 + delta as i64).clamp(0, max) as usize;
+    }
+
+    pub fn draw(&self, screen: &mut Screen, theme: &Theme) {
+        screen.fill_row(0, theme.header());
+        let header = format!(
+            "Search \"{}\": {} results ({}/{})  Esc to close",
+            self.query,
+            self.hits.len(),
+            (self.scroll + 1).min(self.hits.len()),
+            self.hits.len(),
+        );
+        screen.print_colored(0, 1, &header, theme.text(), theme.header());
+
+        let width = screen.cols().saturating_sub(1).max(1) as usize;
+        let rows = screen.rows();
+        let mut row = 1;
+        for hit in self.hits.iter().skip(self.scroll) {
+            let meta = format!("{} #{} {}: ", local_time(hit.timestamp, "%Y-%m-%d %H:%M"), hit.channel, hit.sender);
+
+            // Colour every char of the line, then wrap it like the chat view does
+            let mut line: Vec<(char, [f32; 4], [f32; 3])> = meta.chars()
+                .map(|c| (c, theme.meta(), theme.background()))
+                .collect();
+            for (i, c) in hit.text.chars().enumerate() {
+                if hit.matches.iter().any(|m| m.contains(&i)) {
+                    line.push((c, MATCH_FG, MATCH_BG));
+                } else {
+                    line.push((c, theme.text(), theme.background()));
+                }
+            }
+
+            for chunk in line.chunks(width) {
+                if row >= rows {
+                    return;
+                }
+                for (col, (c, fg, bg)) in chunk.iter().enumerate() {
+                    screen.print_colored(row, 1 + col as u32, &c.to_string(), *fg, *bg);
+                }
+                row += 1;
+            }
+        }
+    }
+}
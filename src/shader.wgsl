@@ -2,7 +2,10 @@
 struct ProjectionUniform {
   cell_dim: vec2<f32>;
   size: vec2<f32>;
-  offet: vec2<f32>;
+  // Pixels that scrolling cells are moved by, for scrolling part of a row
+  offset: vec2<f32>;
+  // Scrolling cells are cut off outside these pixel rows, top and bottom
+  clip: vec2<f32>;
 };
 [[group(0), binding(0)]]
 var<uniform> projection: ProjectionUniform;
@@ -18,13 +21,19 @@ struct InstanceInput {
   [[location(8)]] bg_color: vec3<f32>;
   [[location(9)]] fg_color: vec4<f32>;
   [[location(10)]] glyph_pos: vec4<f32>;
+  [[location(11)]] scrolls: f32;
 };
 
 struct BGOutput {
   [[builtin(position)]] clip_position: vec4<f32>;
   [[location(1)]] color: vec3<f32>;
+  [[location(2)]] scrolls: f32;
 };
 
+fn clipped(position: vec4<f32>, scrolls: f32) -> bool {
+  return scrolls > 0.5 && (position.y < projection.clip.x || position.y >= projection.clip.y);
+}
+
 [[stage(vertex)]]
 fn vs_bg(
   model: VertexInput,
@@ -36,16 +45,39 @@ fn vs_bg(
   let pos: vec2<f32> = instance.cell_coords * projection.cell_dim;
   // Pixel offsets
   let size: vec2<f32> = model.position * projection.cell_dim;
+  let scroll: vec2<f32> = projection.offset * instance.scrolls;
+
+  var translated: vec2<f32> = ((pos + size + scroll) * vec2<f32>(2.0/projection.size.x, -2.0/projection.size.y)) + vec2<f32>(-1.0, 1.0);
+
+  out.clip_position = vec4<f32>(translated, 0.0, 1.0);
+  out.color = instance.bg_color;
+  out.scrolls = instance.scrolls;
+  return out;
+}
 
-  var translated: vec2<f32> = ((pos + size) * vec2<f32>(2.0/projection.size.x, -2.0/projection.size.y)) + vec2<f32>(-1.0, 1.0);
+// Solid rectangles placed in pixels rather than cells, glyph_pos holds x, y, width and height
+[[stage(vertex)]]
+fn vs_rect(
+  model: VertexInput,
+  instance: InstanceInput,
+) -> BGOutput {
+  var out: BGOutput;
+
+  let pos: vec2<f32> = instance.glyph_pos.xy + model.position * instance.glyph_pos.zw + projection.offset * instance.scrolls;
+
+  var translated: vec2<f32> = (pos * vec2<f32>(2.0/projection.size.x, -2.0/projection.size.y)) + vec2<f32>(-1.0, 1.0);
 
   out.clip_position = vec4<f32>(translated, 0.0, 1.0);
   out.color = instance.bg_color;
+  out.scrolls = instance.scrolls;
   return out;
 }
 
 [[stage(fragment)]]
 fn fs_bg(in: BGOutput) -> [[location(0)]] vec4<f32> {
+  if (clipped(in.clip_position, in.scrolls)) {
+    discard;
+  }
   return vec4<f32>(in.color, 1.0);
 }
 
@@ -53,6 +85,7 @@ struct VertexOutput {
   [[builtin(position)]] clip_position: vec4<f32>;
   [[location(0)]] tex_coords: vec2<f32>;
   [[location(1)]] color: vec4<f32>;
+  [[location(2)]] scrolls: f32;
 };
 
 [[stage(vertex)]]
@@ -71,11 +104,13 @@ fn vs_main(
   let top_offset = (projection.cell_dim.y - instance.glyph_pos.y);
   let left_offset = instance.glyph_pos.x;
   let cell_offset = vec2<f32>(left_offset, top_offset);
+  let scroll: vec2<f32> = projection.offset * instance.scrolls;
 
   // This vertex's position translated to cell and with glyph offsets and projected to screen space;
-  var translated: vec2<f32> = ((pos + size + cell_offset) * vec2<f32>(2.0/projection.size.x, -2.0/projection.size.y)) + vec2<f32>(-1.0, 1.0);
+  var translated: vec2<f32> = ((pos + size + cell_offset + scroll) * vec2<f32>(2.0/projection.size.x, -2.0/projection.size.y)) + vec2<f32>(-1.0, 1.0);
 
   out.color = instance.fg_color;
+  out.scrolls = instance.scrolls;
   out.tex_coords = (model.position * instance.tex_size) + instance.tex_offset;
   out.clip_position = vec4<f32>(translated, 0.0, 1.0);
   return out;
@@ -88,7 +123,10 @@ var s_diffuse: sampler;
 
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-
+  // Sampled before discarding, sampling has to happen in uniform control flow
   let tex_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+  if (clipped(in.clip_position, in.scrolls)) {
+    discard;
+  }
   return tex_color * in.color;
 }
@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// How long a message stays eligible to absorb repeats after it was last seen.
+const WINDOW: Duration = Duration::from_secs(30);
+// How many distinct recent messages we compare against.
+const MAX_TRACKED: usize = 50;
+// Two normalized messages at least this similar are treated as the same spam.
+const SIMILARITY: f32 = 0.9;
+// Edit distance is quadratic, longer messages only collapse when identical.
+const MAX_FUZZY_LEN: usize = 200;
+
+struct Recent {
+    // Runs only fold within one channel, muting a channel mustn't hide another's messages
+    channel: String,
+    key: Vec<char>,
+    line: String,
+    id: usize,
+    count: u32,
+    last_seen: Instant,
+}
+
+/// Detects identical or near-identical messages within a sliding window so they can be folded
+/// into a single line with a repeat counter.
+pub struct SpamDetector {
+    recent: VecDeque<Recent>,
+}
+
+impl SpamDetector {
+    pub fn new() -> Self {
+        Self {
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// If `text` repeats a message seen in `channel` within the window, bumps its repeat count
+    /// and returns the id of the original message along with the line it should be rewritten to.
+    pub fn check(&mut self, channel: &str, text: &str, now: Instant) -> Option<(usize, String)> {
+        self.recent.retain(|r| now.duration_since(r.last_seen) < WINDOW);
+
+        let key = normalize(text);
+        if key.is_empty() {
+            return None;
+        }
+        let r = self.recent.iter_mut().rev().find(|r| r.channel == channel && similar(&r.key, &key))?;
+        r.count += 1;
+        r.last_seen = now;
+        Some((r.id, format!("{} ×{}", r.line, r.count)))
+    }
+
+    /// Remembers a freshly displayed message so later repeats can be folded into it. `line` is
+    /// what was displayed for it.
+    pub fn record(&mut self, channel: &str, text: &str, line: String, id: usize, now: Instant) {
+        let key = normalize(text);
+        if key.is_empty() {
+            return;
+        }
+        if self.recent.len() >= MAX_TRACKED {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(Recent {
+            channel: channel.to_string(),
+            key,
+            line,
+            id,
+            count: 1,
+            last_seen: now,
+        });
+    }
+}
+
+/// Lowercases, collapses whitespace and strips the invisible characters and trailing punctuation
+/// people use to dodge Twitch's duplicate message check.
+fn normalize(text: &str) -> Vec<char> {
+    let cleaned: String = text
+        .chars()
+        .filter(|c| !matches!(c, '\u{E0000}' | '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}'))
+        .collect();
+    let mut key: Vec<char> = cleaned
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .chars()
+        .collect();
+    while key.last().is_some_and(|c| c.is_ascii_punctuation()) {
+        key.pop();
+    }
+    key
+}
+
+fn similar(a: &[char], b: &[char]) -> bool {
+    if a == b {
+        return true;
+    }
+    let longest = a.len().max(b.len());
+    if longest > MAX_FUZZY_LEN {
+        return false;
+    }
+    // Cheap bail out before doing the full edit distance
+    let len_diff = a.len().abs_diff(b.len());
+    if 1.0 - (len_diff as f32 / longest as f32) < SIMILARITY {
+        return false;
+    }
+    1.0 - (levenshtein(a, b) as f32 / longest as f32) >= SIMILARITY
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPAM: &str = "buy cheap followers at example dot com";
+
+    fn key(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn normalizing_strips_dodges() {
+        assert_eq!(normalize("  Hello \u{200b}  WORLD!!! \u{e0000}"), key("hello world"));
+        assert_eq!(normalize("wait... what?"), key("wait... what"));
+        assert!(normalize("?!").is_empty());
+    }
+
+    #[test]
+    fn near_duplicates_are_similar() {
+        assert_eq!(levenshtein(&key("kitten"), &key("sitting")), 3);
+        assert!(similar(&key(SPAM), &key(SPAM)));
+        // One edit in 38 characters is close enough, four isn't
+        assert!(similar(&key(SPAM), &key("buy cheap followers at example dot co")));
+        assert!(similar(&key(SPAM), &key("buy cheap fol1owers at example dot com")));
+        assert!(!similar(&key(SPAM), &key("buy cheap viewers at example dot com")));
+        assert!(!similar(&key("hello"), &key("hallo")));
+
+        // Past the fuzzy length only identical messages match
+        let long = "a".repeat(MAX_FUZZY_LEN + 1);
+        assert!(similar(&key(&long), &key(&long)));
+        assert!(!similar(&key(&long), &key(&format!("{}b", &long[1..]))));
+    }
+
+    #[test]
+    fn repeats_fold_into_the_first() {
+        let now = Instant::now();
+        let mut spam = SpamDetector::new();
+        assert_eq!(spam.check("chan", SPAM, now), None);
+        spam.record("chan", SPAM, "bot: spam".to_string(), 7, now);
+        assert_eq!(spam.check("chan", &SPAM.to_uppercase(), now), Some((7, "bot: spam ×2".to_string())));
+        assert_eq!(spam.check("chan", &format!("{} !!", SPAM), now), Some((7, "bot: spam ×3".to_string())));
+        // Only within the channel it was said in
+        assert_eq!(spam.check("other", SPAM, now), None);
+    }
+
+    #[test]
+    fn window_slides_with_each_repeat() {
+        let start = Instant::now();
+        let mut spam = SpamDetector::new();
+        spam.record("chan", SPAM, "spam".to_string(), 1, start);
+        let almost = start + WINDOW - Duration::from_millis(1);
+        assert!(spam.check("chan", SPAM, almost).is_some());
+        // The repeat kept it alive past the original window
+        assert!(spam.check("chan", SPAM, start + WINDOW).is_some());
+        assert_eq!(spam.check("chan", SPAM, almost + WINDOW * 2), None);
+    }
+
+    #[test]
+    fn window_ends_exactly_after_last_seen() {
+        let start = Instant::now();
+        let mut spam = SpamDetector::new();
+        spam.record("chan", SPAM, "spam".to_string(), 1, start);
+        assert_eq!(spam.check("chan", SPAM, start + WINDOW), None);
+    }
+
+    #[test]
+    fn only_recent_messages_are_tracked() {
+        let now = Instant::now();
+        let mut spam = SpamDetector::new();
+        spam.record("chan", "?!", "blank".to_string(), 0, now);
+        // Nothing alike, so each is tracked on its own
+        let message = |id: usize| id.to_string().repeat(20);
+        for id in 1..=MAX_TRACKED + 1 {
+            spam.record("chan", &message(id), String::new(), id, now);
+        }
+        assert_eq!(spam.recent.len(), MAX_TRACKED);
+        assert_eq!(spam.check("chan", &message(1), now), None);
+        assert!(spam.check("chan", &message(2), now).is_some());
+    }
+}
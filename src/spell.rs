@@ -0,0 +1,569 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use crate::chat::ChatMessage;
+
+// Spell checking the input line against Hunspell dictionaries. The language comes from
+// SPELL_LANG, or LANG when that isn't set, e.g. `en_US`, and `<lang>.dic` and `<lang>.aff` are
+// looked for in the config dir's `dictionaries` then the usual system places. Words that should
+// never be flagged go in a `words` file in the config dir, one per line; names and emotes seen
+// in chat are added for us.
+//
+// Only the parts of the format that matter for checking single words are read: affix rules with
+// their continuation flags, flag aliases, SET, TRY, REP, NEEDAFFIX, FORBIDDENWORD and
+// ONLYINCOMPOUND. Compounding and morphology are ignored, so roots and affixes that only exist
+// for compounds never make a word.
+
+const DEFAULT_LANG: &str = "en_US";
+const SYSTEM_DIRS: &[&str] = &["/usr/share/hunspell", "/usr/share/myspell", "/usr/share/myspell/dicts"];
+
+const MAX_SUGGESTIONS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FlagType {
+    Char,
+    Long,
+    Num,
+}
+
+/// A prefix or suffix rule: remove `strip` from the root and put `add` on, for roots matching
+/// `condition`.
+struct Affix {
+    flag: String,
+    strip: String,
+    add: String,
+    condition: Option<Regex>,
+    cross_product: bool,
+    // Flags the affixed word carries on, e.g. the outer suffix of a twofold suffix
+    continuation: Vec<String>,
+}
+
+impl Affix {
+    /// The root `word` would come from if it was made with this suffix.
+    fn root_of_suffixed(&self, word: &str) -> Option<String> {
+        let stem = word.strip_suffix(self.add.as_str())?;
+        if stem.is_empty() && self.strip.is_empty() {
+            return None;
+        }
+        let root = format!("{}{}", stem, self.strip);
+        self.condition.as_ref().is_none_or(|c| c.is_match(&root)).then_some(root)
+    }
+
+    fn root_of_prefixed(&self, word: &str) -> Option<String> {
+        let stem = word.strip_prefix(self.add.as_str())?;
+        if stem.is_empty() && self.strip.is_empty() {
+            return None;
+        }
+        let root = format!("{}{}", self.strip, stem);
+        self.condition.as_ref().is_none_or(|c| c.is_match(&root)).then_some(root)
+    }
+}
+
+/// Decodes a dictionary file in the encoding its .aff declares with SET, None for encodings we
+/// can't read.
+fn decode(bytes: &[u8], encoding: &str) -> Option<String> {
+    // The ISO8859-15 code points that differ from ISO8859-1
+    let latin9 = |b: u8| match b {
+        0xa4 => '€',
+        0xa6 => 'Š',
+        0xa8 => 'š',
+        0xb4 => 'Ž',
+        0xb8 => 'ž',
+        0xbc => 'Œ',
+        0xbd => 'œ',
+        0xbe => 'Ÿ',
+        b => b as char,
+    };
+    match encoding.to_ascii_uppercase().as_str() {
+        "UTF-8" | "UTF8" => String::from_utf8(bytes.to_vec()).ok().map(|s| s.trim_start_matches('\u{feff}').to_string()),
+        "ISO8859-1" | "ISO-8859-1" => Some(bytes.iter().map(|b| *b as char).collect()),
+        "ISO8859-15" | "ISO-8859-15" => Some(bytes.iter().map(|b| latin9(*b)).collect()),
+        _ => None,
+    }
+}
+
+fn split_flags(flags: &str, flag_type: FlagType) -> Vec<String> {
+    match flag_type {
+        FlagType::Char => flags.chars().map(String::from).collect(),
+        FlagType::Long => flags.chars().collect::<Vec<_>>().chunks(2).map(|c| c.iter().collect()).collect(),
+        FlagType::Num => flags.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect(),
+    }
+}
+
+pub struct Dictionary {
+    // Every root word with the affix flags it takes
+    words: HashMap<String, Vec<String>>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+    // Letters to try when guessing, most common first
+    try_chars: Vec<char>,
+    // Common mistakes, (typed, meant)
+    replacements: Vec<(String, String)>,
+    // Roots with this are only words once an affix is added
+    need_affix: Option<String>,
+    // Words with this are wrong, even when affix rules would make them
+    forbidden: Option<String>,
+    // Roots and affixes with this only appear inside compounds, which we don't make
+    only_in_compound: Option<String>,
+}
+
+impl Dictionary {
+    pub fn load(dic: &Path, aff: &Path) -> Option<Self> {
+        let aff_bytes = fs::read(aff).ok()?;
+        let dic_bytes = fs::read(dic).ok()?;
+        // Hunspell's default when there is no SET
+        let encoding = String::from_utf8_lossy(&aff_bytes).lines()
+            .find_map(|l| l.strip_prefix("SET ").map(|e| e.trim().to_string()))
+            .unwrap_or_else(|| "ISO8859-1".to_string());
+        match (decode(&aff_bytes, &encoding), decode(&dic_bytes, &encoding)) {
+            (Some(aff), Some(dic)) => Some(Self::parse(&dic, &aff)),
+            _ => {
+                println!("{} isn't valid {}", aff.display(), encoding);
+                None
+            },
+        }
+    }
+
+    fn parse(dic: &str, aff: &str) -> Self {
+        let mut dictionary = Self {
+            words: HashMap::new(),
+            prefixes: Vec::new(),
+            suffixes: Vec::new(),
+            try_chars: Vec::new(),
+            replacements: Vec::new(),
+            need_affix: None,
+            forbidden: None,
+            only_in_compound: None,
+        };
+
+        let mut flag_type = FlagType::Char;
+        let mut aliases: Vec<Vec<String>> = Vec::new();
+        // Cross product setting of each affix flag, from the rule's header line
+        let mut cross: HashMap<(String, String), bool> = HashMap::new();
+        // Flags on a .dic entry or after an affix's slash, either written out or an AF alias number
+        let flags_of = |flags: &str, flag_type: FlagType, aliases: &[Vec<String>]| match flags.parse::<usize>() {
+            Ok(n) if !aliases.is_empty() => aliases.get(n.wrapping_sub(1)).cloned().unwrap_or_default(),
+            _ => split_flags(flags, flag_type),
+        };
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["FLAG", "long", ..] => flag_type = FlagType::Long,
+                ["FLAG", "num", ..] => flag_type = FlagType::Num,
+                ["NEEDAFFIX" | "PSEUDOROOT", flag, ..] => dictionary.need_affix = Some(flag.to_string()),
+                ["FORBIDDENWORD", flag, ..] => dictionary.forbidden = Some(flag.to_string()),
+                ["ONLYINCOMPOUND", flag, ..] => dictionary.only_in_compound = Some(flag.to_string()),
+                ["TRY", chars, ..] => dictionary.try_chars = chars.chars().collect(),
+                ["REP", from, to, ..] => dictionary.replacements.push((from.replace('_', " "), to.replace('_', " "))),
+                ["AF", flags, ..] if flags.parse::<usize>().is_err() => aliases.push(split_flags(flags, flag_type)),
+                [kind @ ("PFX" | "SFX"), flag, cross_product, count] if count.parse::<usize>().is_ok() => {
+                    cross.insert((kind.to_string(), flag.to_string()), *cross_product == "Y");
+                },
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                    let strip = if *strip == "0" { "" } else { strip };
+                    // Affixes can carry flags of their own after a slash, for twofold suffixes
+                    let (add, continuation) = add.split_once('/').unwrap_or((add, ""));
+                    let add = if add == "0" { "" } else { add };
+                    let condition = match rest.first() {
+                        None | Some(&".") => None,
+                        Some(c) if *kind == "SFX" => Regex::new(&format!("{}$", c)).ok(),
+                        Some(c) => Regex::new(&format!("^{}", c)).ok(),
+                    };
+                    let affix = Affix {
+                        flag: flag.to_string(),
+                        strip: strip.to_string(),
+                        add: add.to_string(),
+                        condition,
+                        cross_product: cross.get(&(kind.to_string(), flag.to_string())).copied().unwrap_or(false),
+                        continuation: flags_of(continuation, flag_type, &aliases),
+                    };
+                    if *kind == "PFX" {
+                        dictionary.prefixes.push(affix);
+                    } else {
+                        dictionary.suffixes.push(affix);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        // The first line is a word count
+        for line in dic.lines().skip(1) {
+            // Morphological fields follow a tab or space
+            let entry = line.split(['\t', ' ']).next().unwrap_or("");
+            let (word, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            if word.is_empty() {
+                continue;
+            }
+            let flags = flags_of(flags, flag_type, &aliases);
+            dictionary.words.entry(word.to_string()).or_default().extend(flags);
+        }
+        dictionary
+    }
+
+    /// Whether `flags` include `special`, one of the flags the .aff gives a meaning.
+    fn is(flags: &[String], special: &Option<String>) -> bool {
+        special.as_ref().is_some_and(|s| flags.contains(s))
+    }
+
+    /// Whether `root` is in the dictionary with all of `flags`, and can be used outside a compound.
+    fn root_takes(&self, root: &str, flags: &[&str]) -> bool {
+        self.words.get(root).is_some_and(|f| {
+            !Self::is(f, &self.forbidden)
+                && !Self::is(f, &self.only_in_compound)
+                && flags.iter().all(|flag| f.iter().any(|g| g == flag))
+        })
+    }
+
+    /// Whether an affix can be the last one added, rather than only leading on to another.
+    fn outermost(&self, affix: &Affix) -> bool {
+        !Self::is(&affix.continuation, &self.need_affix) && !Self::is(&affix.continuation, &self.only_in_compound)
+    }
+
+    /// Whether `stem` can take `suffix` along with `flags`: a root with its flag, or a root with
+    /// an inner suffix whose continuation allows it.
+    fn takes_suffix(&self, stem: &str, suffix: &Affix, flags: &[&str]) -> bool {
+        let root_takes = |root: &str, flag: &str| {
+            let mut needs = flags.to_vec();
+            needs.push(flag);
+            self.root_takes(root, &needs)
+        };
+        root_takes(stem, &suffix.flag) || self.suffixes.iter()
+            .filter(|inner| inner.continuation.contains(&suffix.flag) && !Self::is(&inner.continuation, &self.only_in_compound))
+            .any(|inner| inner.root_of_suffixed(stem).is_some_and(|root| root_takes(&root, &inner.flag)))
+    }
+
+    /// Whether `word` is a root, or a root with a prefix, up to two suffixes, or both.
+    fn check_form(&self, word: &str) -> bool {
+        if let Some(flags) = self.words.get(word) {
+            if Self::is(flags, &self.forbidden) {
+                return false;
+            }
+            if !Self::is(flags, &self.need_affix) && !Self::is(flags, &self.only_in_compound) {
+                return true;
+            }
+        }
+        let outer_suffixes = || self.suffixes.iter().filter(|s| self.outermost(s));
+        if outer_suffixes().any(|s| s.root_of_suffixed(word).is_some_and(|stem| self.takes_suffix(&stem, s, &[]))) {
+            return true;
+        }
+        self.prefixes.iter().filter(|p| self.outermost(p)).any(|p| {
+            let rest = match p.root_of_prefixed(word) {
+                Some(rest) => rest,
+                None => return false,
+            };
+            self.root_takes(&rest, &[&p.flag]) || (p.cross_product && outer_suffixes()
+                .filter(|s| s.cross_product)
+                .any(|s| s.root_of_suffixed(&rest).is_some_and(|stem| self.takes_suffix(&stem, s, &[&p.flag]))))
+        })
+    }
+
+    /// Checks a word as typed, allowing a capital at the start of a sentence or shouting.
+    pub fn check(&self, word: &str) -> bool {
+        let lower = word.to_lowercase();
+        self.check_form(word) || self.check_form(&lower) || self.check_form(&capitalize(&lower))
+    }
+
+    /// Words close to a misspelled one: known mistakes, then one edit away, then two words run
+    /// together.
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let try_chars: Vec<char> = if self.try_chars.is_empty() {
+            ('a'..='z').collect()
+        } else {
+            self.try_chars.clone()
+        };
+        let join = |parts: &[&[char]]| parts.iter().flat_map(|p| p.iter()).collect::<String>();
+
+        let mut candidates = Vec::new();
+        for (from, to) in &self.replacements {
+            for (i, _) in word.match_indices(from.as_str()) {
+                candidates.push(format!("{}{}{}", &word[..i], to, &word[i + from.len()..]));
+            }
+        }
+        for i in 0..chars.len().saturating_sub(1) {
+            let mut swapped = chars.clone();
+            swapped.swap(i, i + 1);
+            candidates.push(swapped.iter().collect());
+        }
+        for i in 0..chars.len() {
+            candidates.push(join(&[&chars[..i], &chars[i + 1..]]));
+        }
+        for i in 0..chars.len() {
+            for c in &try_chars {
+                candidates.push(join(&[&chars[..i], &[*c], &chars[i + 1..]]));
+            }
+        }
+        for i in 0..=chars.len() {
+            for c in &try_chars {
+                candidates.push(join(&[&chars[..i], &[*c], &chars[i..]]));
+            }
+        }
+        for i in 1..chars.len() {
+            candidates.push(format!("{} {}", join(&[&chars[..i]]), join(&[&chars[i..]])));
+        }
+
+        let capital = chars.first().is_some_and(|c| c.is_uppercase());
+        let mut seen = HashSet::new();
+        candidates.into_iter()
+            .filter(|c| c != word && c.split(' ').all(|w| !w.is_empty() && self.check(w)))
+            .map(|c| if capital { capitalize(&c) } else { c })
+            // TRY has capitals too, which only make case variants of the same word
+            .filter(|c| seen.insert(c.to_lowercase()))
+            .take(MAX_SUGGESTIONS)
+            .collect()
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn find_dictionary(lang: &str) -> Option<(PathBuf, PathBuf)> {
+    let mut dirs = vec![crate::config::config_dir().join("dictionaries")];
+    dirs.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+    dirs.into_iter()
+        .map(|dir| (dir.join(format!("{}.dic", lang)), dir.join(format!("{}.aff", lang))))
+        .find(|(dic, aff)| dic.exists() && aff.exists())
+}
+
+/// Finds misspelled words in the input line, skipping anything that isn't prose.
+pub struct SpellChecker {
+    dictionary: Dictionary,
+    // Lowercased words never to flag
+    ignored: HashSet<String>,
+}
+
+impl SpellChecker {
+    /// Loads the dictionary for the configured language, None when there isn't one.
+    pub fn load() -> Option<Self> {
+        let lang = env::var("SPELL_LANG").ok()
+            .or_else(|| env::var("LANG").ok().map(|l| l.split('.').next().unwrap_or("").to_string()))
+            .filter(|l| !l.is_empty() && l != "C" && l != "POSIX")
+            .unwrap_or_else(|| DEFAULT_LANG.to_string());
+        let (dic, aff) = match find_dictionary(&lang) {
+            Some(paths) => paths,
+            None => {
+                println!("No {} dictionary found, spell checking is off", lang);
+                return None;
+            }
+        };
+        let dictionary = match Dictionary::load(&dic, &aff) {
+            Some(d) => d,
+            None => {
+                println!("Failed to read {}, spell checking is off", dic.display());
+                return None;
+            }
+        };
+        let ignored = fs::read_to_string(crate::config::config_dir().join("words"))
+            .unwrap_or_default()
+            .lines()
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        Some(Self { dictionary, ignored })
+    }
+
+    /// Learns the names and emotes in a message so they aren't flagged when we type them.
+    pub fn saw_message(&mut self, m: &ChatMessage) {
+        if m.sender.is_empty() {
+            return;
+        }
+        self.ignored.insert(m.sender.to_lowercase());
+        self.ignored.insert(m.display_name().to_lowercase());
+        let chars: Vec<char> = m.message.chars().collect();
+        for emote in m.emotes() {
+            if let Some(code) = chars.get(emote.range) {
+                self.ignored.insert(code.iter().collect::<String>().to_lowercase());
+            }
+        }
+    }
+
+    /// Byte ranges of the misspelled words in `text`. The word `typing_at` is in, if any, is left
+    /// alone until it's finished.
+    pub fn misspelled(&self, text: &str, typing_at: Option<usize>) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        for token in text.split_whitespace() {
+            let offset = token.as_ptr() as usize - text.as_ptr() as usize;
+            // Mentions, commands, links and anything with numbers in it aren't words
+            if token.starts_with(['@', '/']) || token.contains("://") || token.starts_with("www.") || token.contains(|c: char| c.is_ascii_digit()) {
+                continue;
+            }
+            if typing_at.is_some_and(|at| at >= offset && at <= offset + token.len()) {
+                continue;
+            }
+            // Hyphens and other punctuation separate words, apostrophes are part of them
+            for word in token.split(|c: char| !c.is_alphabetic() && c != '\'') {
+                let word = word.trim_matches('\'');
+                if word.is_empty() || self.ignored.contains(&word.to_lowercase()) || self.dictionary.check(word) {
+                    continue;
+                }
+                let start = word.as_ptr() as usize - text.as_ptr() as usize;
+                ranges.push(start..start + word.len());
+            }
+        }
+        ranges
+    }
+
+    /// The misspelling to fix next: the one at or before `cursor`, else the first after it.
+    pub fn word_to_fix(&self, text: &str, cursor: usize) -> Option<Range<usize>> {
+        let ranges = self.misspelled(text, None);
+        ranges.iter().rev().find(|r| r.start <= cursor).or_else(|| ranges.first()).cloned()
+    }
+
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        self.dictionary.suggest(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFF: &str = "SET UTF-8
+TRY esianrtolcdugmphbyfvkwz
+REP 1
+REP alot a_lot
+NEEDAFFIX N
+FORBIDDENWORD F
+ONLYINCOMPOUND C
+PFX U Y 1
+PFX U 0 un .
+SFX S Y 2
+SFX S 0 s [^y]
+SFX S y ies y
+SFX H Y 1
+SFX H 0 ful/L .
+SFX L Y 1
+SFX L 0 ly .
+SFX G Y 1
+SFX G 0 ing/C .
+";
+
+    const DIC: &str = "12
+hello
+world
+a
+lot
+cat/S
+cats/F
+lock/US
+city/S
+hope/H
+fish/NS
+foo/C
+sing/G
+";
+
+    fn dictionary() -> Dictionary {
+        Dictionary::parse(DIC, AFF)
+    }
+
+    fn checker() -> SpellChecker {
+        SpellChecker { dictionary: dictionary(), ignored: HashSet::new() }
+    }
+
+    // The words `misspelled` flags in `text`
+    fn flagged<'a>(checker: &SpellChecker, text: &'a str, typing_at: Option<usize>) -> Vec<&'a str> {
+        checker.misspelled(text, typing_at).into_iter().map(|r| &text[r]).collect()
+    }
+
+    #[test]
+    fn roots_and_case() {
+        let d = dictionary();
+        assert!(d.check("hello"));
+        assert!(d.check("Hello"));
+        assert!(d.check("HELLO"));
+        assert!(!d.check("helo"));
+    }
+
+    #[test]
+    fn affixes_follow_their_conditions() {
+        let d = dictionary();
+        assert!(d.check("cities"));
+        assert!(!d.check("citys"));
+        assert!(d.check("locks"));
+        assert!(d.check("unlock"));
+        // Prefix and suffix together, both cross product
+        assert!(d.check("unlocks"));
+        assert!(!d.check("uncities"));
+    }
+
+    #[test]
+    fn continuation_flags_allow_a_second_suffix() {
+        let d = dictionary();
+        assert!(d.check("hopeful"));
+        assert!(d.check("hopefully"));
+        assert!(!d.check("hopely"));
+    }
+
+    #[test]
+    fn special_flags() {
+        let d = dictionary();
+        // Derivable but forbidden
+        assert!(d.check("cat"));
+        assert!(!d.check("cats"));
+        // Only a word with an affix
+        assert!(!d.check("fish"));
+        assert!(d.check("fishs"));
+        // Only in compounds, as a root and as an affix
+        assert!(!d.check("foo"));
+        assert!(d.check("sing"));
+        assert!(!d.check("singing"));
+    }
+
+    #[test]
+    fn suggestions() {
+        let d = dictionary();
+        assert_eq!(d.suggest("helo")[0], "hello");
+        assert!(d.suggest("Wrold").contains(&"World".to_string()));
+        assert!(d.suggest("alot").contains(&"a lot".to_string()));
+        assert!(d.suggest("lcok").contains(&"lock".to_string()));
+        assert!(!d.suggest("catz").contains(&"cats".to_string()));
+        assert!(d.suggest("qqqqqq").is_empty());
+    }
+
+    #[test]
+    fn misspelled_ranges_skip_what_isnt_prose() {
+        let checker = checker();
+        let text = "helo @wrld /wrld https://wrld.example wrld2 hello-wrld 'helo' héllo";
+        assert_eq!(flagged(&checker, text, None), ["helo", "wrld", "helo", "héllo"]);
+        let ranges = checker.misspelled(text, None);
+        assert_eq!(ranges[0], 0..4);
+        assert_eq!(ranges[3], text.len() - 6..text.len());
+    }
+
+    #[test]
+    fn the_word_being_typed_is_left_alone() {
+        let checker = checker();
+        assert_eq!(flagged(&checker, "helo wrld", Some(9)), ["helo"]);
+        assert_eq!(flagged(&checker, "helo wrld", Some(4)), ["wrld"]);
+        assert_eq!(flagged(&checker, "helo wrld", Some(0)), ["wrld"]);
+        assert_eq!(checker.word_to_fix("helo wrld", 7), Some(5..9));
+        assert_eq!(checker.word_to_fix("hello wrld", 0), Some(6..10));
+    }
+
+    #[test]
+    fn names_from_chat_are_ignored() {
+        let mut checker = checker();
+        let tags = [("display-name".to_string(), "Wrld".to_string())].into_iter().collect();
+        checker.saw_message(&ChatMessage::sent("chan", "wrld", "hi", tags));
+        assert!(checker.misspelled("hello Wrld", None).is_empty());
+    }
+
+    #[test]
+    fn files_decode_as_set_declares() {
+        assert_eq!(decode(b"caf\xe9", "ISO8859-1").as_deref(), Some("café"));
+        assert_eq!(decode(b"\xa4", "ISO8859-15").as_deref(), Some("€"));
+        assert_eq!(decode("\u{feff}café".as_bytes(), "UTF-8").as_deref(), Some("café"));
+        assert_eq!(decode(b"caf\xe9", "UTF-8"), None);
+        assert_eq!(decode(b"word", "KOI8-R"), None);
+    }
+}
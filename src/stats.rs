@@ -0,0 +1,176 @@
+use std::collections::{HashMap, VecDeque};
+use crate::chat::{self, ChatMessage, MessageKind};
+use crate::renderer::Screen;
+use crate::theme::Theme;
+
+const MINUTE: i64 = 60 * 1000;
+// How much of the event stream is kept around, also the longest window the view can show
+const KEEP_MINUTES: i64 = 60;
+const DEFAULT_WINDOW: i64 = 10;
+const TOP_COUNT: usize = 10;
+// Height of the sparkline in rows
+const GRAPH_ROWS: u32 = 4;
+
+const BAR_COLOR: [f32; 4] = [0.4, 0.6, 1.0, 1.0];
+// Block elements from an eighth of a cell up to a full one, for the bars
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+struct Sample {
+    timestamp: i64,
+    sender: String,
+    emotes: Vec<String>,
+}
+
+/// Chat activity over the last hour, fed from the same messages the chat view sees.
+pub struct Stats {
+    samples: VecDeque<Sample>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, m: &ChatMessage) {
+        if !matches!(m.kind, MessageKind::Chat | MessageKind::Action) {
+            return;
+        }
+        let text: Vec<char> = m.message.chars().collect();
+        let emotes = m.emotes().iter()
+            .filter_map(|e| text.get(e.range.clone()))
+            .map(|code| code.iter().collect())
+            .collect();
+        self.samples.push_back(Sample {
+            timestamp: m.timestamp,
+            sender: m.sender.clone(),
+            emotes,
+        });
+
+        let cutoff = self.now() - KEEP_MINUTES * MINUTE;
+        while self.samples.front().is_some_and(|s| s.timestamp < cutoff) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// The time the stats are relative to. Live that's the clock, but when the messages are from
+    /// long ago, e.g. a replay, it's the newest message so the numbers still mean something.
+    fn now(&self) -> i64 {
+        let wall = chat::now_millis();
+        match self.samples.back() {
+            Some(s) if wall - s.timestamp > KEEP_MINUTES * MINUTE => s.timestamp,
+            _ => wall,
+        }
+    }
+
+    /// Messages in each of the last `minutes` minutes, oldest first.
+    fn per_minute(&self, now: i64, minutes: usize) -> Vec<usize> {
+        let mut counts = vec![0; minutes];
+        for s in &self.samples {
+            let age = ((now - s.timestamp).max(0) / MINUTE) as usize;
+            if age < minutes {
+                counts[minutes - 1 - age] += 1;
+            }
+        }
+        counts
+    }
+
+    fn since(&self, from: i64) -> impl Iterator<Item = &Sample> {
+        self.samples.iter().filter(move |s| s.timestamp >= from)
+    }
+}
+
+fn top<'a>(items: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for item in items {
+        *counts.entry(item).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts.truncate(TOP_COUNT);
+    counts
+}
+
+/// Full window dashboard over `Stats`.
+pub struct StatsView {
+    // Minutes covered by the totals and top lists
+    window: i64,
+}
+
+impl StatsView {
+    pub fn new() -> Self {
+        Self {
+            window: DEFAULT_WINDOW,
+        }
+    }
+
+    pub fn change_window(&mut self, delta: i64) {
+        self.window = (self.window + delta).clamp(1, KEEP_MINUTES);
+    }
+
+    pub fn draw(&self, screen: &mut Screen, stats: &Stats, theme: &Theme) {
+        let now = stats.now();
+        let from = now - self.window * MINUTE;
+        let messages = stats.since(from).count();
+        let mut chatters: Vec<&str> = stats.since(from).map(|s| s.sender.as_str()).collect();
+        chatters.sort_unstable();
+        chatters.dedup();
+
+        screen.fill_row(0, theme.header());
+        let header = format!("Chat activity, last {} minutes  +/- to change  Esc to close", self.window);
+        screen.print_colored(0, 1, &header, theme.text(), theme.header());
+        screen.print_colored(
+            1, 1,
+            &format!(
+                "{} messages  {:.1}/min  {} unique chatters",
+                messages,
+                messages as f32 / self.window as f32,
+                chatters.len(),
+            ),
+            theme.text(), theme.background(),
+        );
+
+        // One bar per minute, as many minutes as fit across the window
+        let graph_top = 3;
+        let minutes = (screen.cols().saturating_sub(2) as usize).min(KEEP_MINUTES as usize);
+        let counts = stats.per_minute(now, minutes);
+        let peak = counts.iter().copied().max().unwrap_or(0).max(1);
+        screen.print_colored(2, 1, &format!("Messages per minute, last {} minutes (peak {})", minutes, peak), theme.meta(), theme.background());
+        for row in graph_top..graph_top + GRAPH_ROWS {
+            screen.fill(row, 1, minutes as u32, theme.panel());
+        }
+        // Each row of the graph holds eight steps of a bar
+        let steps = GRAPH_ROWS as usize * BARS.len();
+        for (i, count) in counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let height = (steps * *count / peak).max(1);
+            for row in 0..GRAPH_ROWS {
+                let fill = height.saturating_sub(row as usize * BARS.len()).min(BARS.len());
+                if fill == 0 {
+                    break;
+                }
+                let bar = BARS[fill - 1].to_string();
+                screen.print_colored(graph_top + GRAPH_ROWS - 1 - row, 1 + i as u32, &bar, BAR_COLOR, theme.panel());
+            }
+        }
+
+        // Top chatters and emotes side by side under the graph
+        let lists_top = graph_top + GRAPH_ROWS + 1;
+        let column = screen.cols() / 2;
+        let chatters = top(stats.since(from).map(|s| s.sender.as_str()));
+        let emotes = top(stats.since(from).flat_map(|s| s.emotes.iter().map(String::as_str)));
+        for (col, heading, list) in [(1, "Top chatters", chatters), (column, "Top emotes", emotes)] {
+            screen.print_colored(lists_top, col, heading, theme.accent(), theme.background());
+            if list.is_empty() {
+                screen.print_colored(lists_top + 1, col, "nothing yet", theme.meta(), theme.background());
+            }
+            for (i, (name, count)) in list.iter().enumerate() {
+                let line: String = format!("{:>5}  {}", count, name).chars().take(column.saturating_sub(2) as usize).collect();
+                screen.print_colored(lists_top + 1 + i as u32, col, &line, theme.text(), theme.background());
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use crate::chat::{ChatMessage, MessageKind};
+use crate::renderer::TextStyle;
+use crate::results::local_time;
+use crate::view::{rgb, DIM};
+
+// How chat lines are laid out, read from a plain text file:
+//
+//   # what   template
+//   line     {time:%H:%M|dim} {badges|dim} {name|bold}: {text}
+//   action   {time:%H:%M|dim} * {name} {text|italic}
+//   notice   {time:%H:%M|dim} {text|dim}
+//   gutter   6
+//
+// Fields are {time:FORMAT}, {badges}, {name}, {channel} and {text}. After a `|` come colours and
+// styles separated by commas: a #rrggbb colour, `user` for the sender's colour, `dim`, `bold`,
+// `italic` and `underline`. Names are in the sender's colour unless told otherwise. A non-zero
+// gutter pads or cuts the time to that many cells so the names line up.
+
+const DEFAULT_LINE: &str = "{name}: {text}";
+const DEFAULT_ACTION: &str = "* {name} {text|italic}";
+const DEFAULT_NOTICE: &str = "{text}";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Time(String),
+    Badges,
+    Name,
+    Channel,
+    Text,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Color {
+    Default,
+    User,
+    Dim,
+    Rgb([f32; 4]),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Piece {
+    Literal(String),
+    Field { field: Field, color: Color, style: TextStyle },
+}
+
+/// A run of characters in a line drawn with their own colour or style.
+#[derive(Clone, Debug)]
+pub struct Span {
+    // Char range into the line's text
+    pub range: Range<usize>,
+    // None keeps the colour the whole line is drawn with
+    pub fg_color: Option<[f32; 4]>,
+    pub style: TextStyle,
+}
+
+/// A message laid out by a template, ready for the chat view.
+#[derive(Clone, Debug, Default)]
+pub struct StyledLine {
+    pub text: String,
+    pub spans: Vec<Span>,
+    // Where wrapped lines continue from, in cells, so they line up under the text
+    pub indent: usize,
+}
+
+impl StyledLine {
+    /// A line with no styling of its own.
+    pub fn plain(text: String) -> Self {
+        Self { text, ..Self::default() }
+    }
+
+    /// Puts plain text in front of the line, e.g. a marker.
+    pub fn prepend(&mut self, prefix: &str) {
+        let len = prefix.chars().count();
+        self.text.insert_str(0, prefix);
+        for span in &mut self.spans {
+            span.range = span.range.start + len..span.range.end + len;
+        }
+        self.indent += len;
+    }
+
+    fn push(&mut self, text: &str, fg_color: Option<[f32; 4]>, style: TextStyle) {
+        let start = self.text.chars().count();
+        self.text.push_str(text);
+        let end = self.text.chars().count();
+        if fg_color.is_some() || style != TextStyle::default() {
+            self.spans.push(Span { range: start..end, fg_color, style });
+        }
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            pieces.push(Piece::Literal(rest[..open].to_string()));
+        }
+        let close = rest[open..].find('}').ok_or("unclosed {")? + open;
+        let inner = &rest[open + 1..close];
+        rest = &rest[close + 1..];
+
+        let (spec, modifiers) = inner.split_once('|').unwrap_or((inner, ""));
+        let (name, arg) = spec.split_once(':').unwrap_or((spec, ""));
+        let field = match name.trim() {
+            "time" => Field::Time(if arg.is_empty() { "%H:%M".to_string() } else { arg.to_string() }),
+            "badges" => Field::Badges,
+            "name" => Field::Name,
+            "channel" => Field::Channel,
+            "text" => Field::Text,
+            other => return Err(format!("unknown field {{{}}}", other)),
+        };
+        let mut color = if field == Field::Name { Color::User } else { Color::Default };
+        let mut style = TextStyle::default();
+        for modifier in modifiers.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            match modifier {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "dim" => color = Color::Dim,
+                "user" => color = Color::User,
+                hex => color = Color::Rgb(parse_hex(hex).ok_or_else(|| format!("unknown style {:?}", hex))?),
+            }
+        }
+        pieces.push(Piece::Field { field, color, style });
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Literal(rest.to_string()));
+    }
+    Ok(pieces)
+}
+
+fn parse_hex(s: &str) -> Option<[f32; 4]> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let v = u32::from_str_radix(hex, 16).ok()?;
+    Some(rgb([(v >> 16) as u8, (v >> 8) as u8, v as u8]))
+}
+
+/// Short badge names so a line doesn't lose half its width to them.
+fn badge_letters(m: &ChatMessage) -> String {
+    let letters: String = m.badges().iter().filter_map(|(name, _)| match *name {
+        "broadcaster" => Some('B'),
+        "moderator" => Some('M'),
+        "vip" => Some('V'),
+        "subscriber" | "founder" => Some('S'),
+        "staff" | "admin" | "global_mod" => Some('T'),
+        _ => None,
+    }).collect();
+    if letters.is_empty() { letters } else { format!("[{}]", letters) }
+}
+
+pub struct Template {
+    line: Vec<Piece>,
+    action: Vec<Piece>,
+    notice: Vec<Piece>,
+    gutter: usize,
+}
+
+impl Template {
+    /// Reads the templates, keeping the defaults for anything missing or broken.
+    pub fn load(path: &Path) -> Self {
+        let mut template = Self {
+            line: parse(DEFAULT_LINE).unwrap(),
+            action: parse(DEFAULT_ACTION).unwrap(),
+            notice: parse(DEFAULT_NOTICE).unwrap(),
+            gutter: 0,
+        };
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            let result = match key {
+                "line" => parse(value).map(|p| template.line = p),
+                "action" => parse(value).map(|p| template.action = p),
+                "notice" => parse(value).map(|p| template.notice = p),
+                "gutter" => value.parse().map(|g| template.gutter = g).map_err(|e| e.to_string()),
+                _ => Err(format!("unknown setting {:?}", key)),
+            };
+            if let Err(e) = result {
+                println!("{}:{}: {}", path.display(), n + 1, e);
+            }
+        }
+        template
+    }
+
+    /// Lays a message out, replacing the old fixed `sender: message` format.
+    pub fn render(&self, m: &ChatMessage) -> StyledLine {
+        let pieces = match m.kind {
+            MessageKind::Action => &self.action,
+            MessageKind::Notice | MessageKind::UserNotice if m.sender.is_empty() => &self.notice,
+            _ => &self.line,
+        };
+
+        let mut line = StyledLine::default();
+        let mut skip_space = false;
+        for piece in pieces {
+            match piece {
+                Piece::Literal(text) => {
+                    // Don't leave a double space where an empty field was
+                    let text = if skip_space { text.strip_prefix(' ').unwrap_or(text) } else { text };
+                    line.push(text, None, TextStyle::default());
+                    skip_space = false;
+                },
+                Piece::Field { field, color, style } => {
+                    let text = match field {
+                        Field::Time(format) => {
+                            let time = local_time(m.timestamp, format);
+                            if self.gutter > 0 {
+                                let time: String = time.chars().take(self.gutter).collect();
+                                format!("{:<width$}", time, width = self.gutter)
+                            } else {
+                                time
+                            }
+                        },
+                        Field::Badges => badge_letters(m),
+                        Field::Name => m.display_name().to_string(),
+                        Field::Channel => format!("#{}", m.channel),
+                        Field::Text => {
+                            line.indent = line.text.chars().count();
+                            m.message.clone()
+                        },
+                    };
+                    let fg_color = match color {
+                        Color::Default => None,
+                        Color::User => Some(rgb(m.color())),
+                        Color::Dim => Some(DIM),
+                        Color::Rgb(c) => Some(*c),
+                    };
+                    skip_space = text.is_empty();
+                    line.push(&text, fg_color, *style);
+                },
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Midday on 2024-06-15 UTC, the same year in every time zone
+    const TIMESTAMP: i64 = 1_718_452_800_000;
+
+    fn template(line: &str, gutter: usize) -> Template {
+        Template {
+            line: parse(line).unwrap(),
+            action: parse(DEFAULT_ACTION).unwrap(),
+            notice: parse(DEFAULT_NOTICE).unwrap(),
+            gutter,
+        }
+    }
+
+    fn message(text: &str, tags: &[(&str, &str)]) -> ChatMessage {
+        let tags = tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let mut m = ChatMessage::sent("chan", "someone", text, tags);
+        m.timestamp = TIMESTAMP;
+        m
+    }
+
+    #[test]
+    fn fields_and_literals() {
+        assert_eq!(parse("a {name} b").unwrap(), [
+            Piece::Literal("a ".to_string()),
+            Piece::Field { field: Field::Name, color: Color::User, style: TextStyle::default() },
+            Piece::Literal(" b".to_string()),
+        ]);
+        assert_eq!(parse("{time}").unwrap(), [
+            Piece::Field { field: Field::Time("%H:%M".to_string()), color: Color::Default, style: TextStyle::default() },
+        ]);
+        assert_eq!(parse("{ time:%Y-%m-%d }").unwrap().len(), 1);
+        assert_eq!(parse("").unwrap(), []);
+    }
+
+    #[test]
+    fn modifiers() {
+        let pieces = parse("{text|bold, italic,underline,#ff8000}{name|dim}{channel|user}").unwrap();
+        let style = TextStyle { bold: true, italic: true, underline: true };
+        assert_eq!(pieces, [
+            Piece::Field { field: Field::Text, color: Color::Rgb([1.0, 128.0 / 255.0, 0.0, 1.0]), style },
+            Piece::Field { field: Field::Name, color: Color::Dim, style: TextStyle::default() },
+            Piece::Field { field: Field::Channel, color: Color::User, style: TextStyle::default() },
+        ]);
+    }
+
+    #[test]
+    fn broken_templates() {
+        assert_eq!(parse("{name").unwrap_err(), "unclosed {");
+        assert_eq!(parse("{nick}").unwrap_err(), "unknown field {nick}");
+        assert_eq!(parse("{text|sparkly}").unwrap_err(), "unknown style \"sparkly\"");
+        assert!(parse("{text|#fff}").is_err());
+        assert!(parse("{text|#gggggg}").is_err());
+    }
+
+    #[test]
+    fn renders_fields_with_spans() {
+        let m = message("hello there", &[("display-name", "SomeOne"), ("badges", "moderator/1,subscriber/6"), ("color", "#00FF00")]);
+        let line = template("{time:%Y|dim} {badges} {name}: {text|bold}", 0).render(&m);
+        assert_eq!(line.text, "2024 [MS] SomeOne: hello there");
+        assert_eq!(line.indent, 19);
+        let spans: Vec<_> = line.spans.iter().map(|s| (s.range.clone(), s.fg_color, s.style.bold)).collect();
+        assert_eq!(spans, [
+            (0..4, Some(DIM), false),
+            (10..17, Some([0.0, 1.0, 0.0, 1.0]), false),
+            (19..30, None, true),
+        ]);
+    }
+
+    #[test]
+    fn empty_fields_leave_no_double_space() {
+        let m = message("hi", &[]);
+        let line = template("{badges} {name} {channel}: {text}", 0).render(&m);
+        assert_eq!(line.text, "someone #chan: hi");
+    }
+
+    #[test]
+    fn gutter_pads_and_cuts_the_time() {
+        let m = message("hi", &[]);
+        assert_eq!(template("{time:%Y}|{text}", 6).render(&m).text, "2024  |hi");
+        assert_eq!(template("{time:%Y}|{text}", 2).render(&m).text, "20|hi");
+    }
+
+    #[test]
+    fn kinds_pick_their_template() {
+        let line = template(DEFAULT_LINE, 0);
+        assert_eq!(line.render(&message("/me waves", &[])).text, "* someone waves");
+        let mut notice = message("Now in slow mode", &[]);
+        notice.kind = MessageKind::Notice;
+        notice.sender.clear();
+        assert_eq!(line.render(&notice).text, "Now in slow mode");
+    }
+
+    #[test]
+    fn prepending_shifts_spans() {
+        let mut line = template(DEFAULT_LINE, 0).render(&message("hi", &[]));
+        line.prepend("> ");
+        assert_eq!(line.text, "> someone: hi");
+        assert_eq!(line.spans[0].range, 2..9);
+        assert_eq!(line.indent, 11);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::view::{BG, DIM, NORMAL};
+
+/// Colours for the chat view and everything drawn over it. Lines are pushed with the plain NORMAL,
+/// DIM and BG colours and the theme swaps them when drawing, so switching themes recolours what is
+/// already on screen. Panels and prompts take their colours from the theme as they draw.
+/// Highlights like errors and name colours stay as they are.
+#[derive(Copy, Clone)]
+pub struct Theme {
+    pub name: &'static str,
+    // Behind everything, where no cell is drawn
+    clear: [f32; 3],
+    background: [f32; 3],
+    text: [f32; 4],
+    dim: [f32; 4],
+    // Secondary text like timestamps and key hints
+    meta: [f32; 4],
+    // Headings and notes that should stand out from the text
+    accent: [f32; 4],
+    // Prompts, popups and panels drawn over the chat
+    panel: [f32; 3],
+    // Title bars across the top of panels
+    header: [f32; 3],
+    cursor: [f32; 3],
+    marked: [f32; 3],
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "dark",
+        clear: [0.1, 0.1, 0.1],
+        background: BG,
+        text: NORMAL,
+        dim: DIM,
+        meta: [0.6, 0.6, 0.6, 1.0],
+        accent: [1.0, 0.85, 0.4, 1.0],
+        panel: [0.15, 0.15, 0.2],
+        header: [0.2, 0.2, 0.3],
+        cursor: [0.2, 0.2, 0.35],
+        marked: [0.35, 0.2, 0.1],
+    },
+    Theme {
+        name: "light",
+        clear: [0.93, 0.93, 0.9],
+        background: [0.98, 0.98, 0.96],
+        text: [0.1, 0.1, 0.12, 1.0],
+        dim: [0.1, 0.1, 0.12, 0.45],
+        meta: [0.35, 0.35, 0.4, 1.0],
+        accent: [0.6, 0.35, 0.0, 1.0],
+        panel: [0.88, 0.88, 0.92],
+        header: [0.8, 0.82, 0.9],
+        cursor: [0.78, 0.83, 0.96],
+        marked: [0.98, 0.85, 0.7],
+    },
+    Theme {
+        name: "dusk",
+        clear: [0.09, 0.1, 0.16],
+        background: [0.12, 0.13, 0.2],
+        text: [0.85, 0.87, 0.95, 1.0],
+        dim: [0.55, 0.58, 0.72, 1.0],
+        meta: [0.6, 0.63, 0.75, 1.0],
+        accent: [1.0, 0.8, 0.45, 1.0],
+        panel: [0.17, 0.18, 0.27],
+        header: [0.22, 0.24, 0.36],
+        cursor: [0.26, 0.28, 0.44],
+        marked: [0.42, 0.26, 0.2],
+    },
+    Theme {
+        name: "contrast",
+        clear: [0.0, 0.0, 0.0],
+        background: [0.0, 0.0, 0.0],
+        text: [1.0, 1.0, 1.0, 1.0],
+        dim: [0.75, 0.75, 0.75, 1.0],
+        meta: [0.85, 0.85, 0.85, 1.0],
+        accent: [1.0, 1.0, 0.0, 1.0],
+        panel: [0.1, 0.1, 0.1],
+        header: [0.25, 0.25, 0.25],
+        cursor: [0.0, 0.2, 0.6],
+        marked: [0.5, 0.3, 0.0],
+    },
+];
+
+impl Theme {
+    pub fn find(name: &str) -> Option<Theme> {
+        THEMES.iter().find(|t| t.name.eq_ignore_ascii_case(name)).copied()
+    }
+
+    pub fn clear(&self) -> [f32; 3] {
+        self.clear
+    }
+
+    pub fn text(&self) -> [f32; 4] {
+        self.text
+    }
+
+    pub fn background(&self) -> [f32; 3] {
+        self.background
+    }
+
+    pub fn meta(&self) -> [f32; 4] {
+        self.meta
+    }
+
+    pub fn accent(&self) -> [f32; 4] {
+        self.accent
+    }
+
+    pub fn panel(&self) -> [f32; 3] {
+        self.panel
+    }
+
+    pub fn header(&self) -> [f32; 3] {
+        self.header
+    }
+
+    pub fn cursor(&self) -> [f32; 3] {
+        self.cursor
+    }
+
+    pub fn marked(&self) -> [f32; 3] {
+        self.marked
+    }
+
+    pub fn fg(&self, color: [f32; 4]) -> [f32; 4] {
+        if color == NORMAL {
+            self.text
+        } else if color == DIM {
+            self.dim
+        } else {
+            color
+        }
+    }
+
+    pub fn bg(&self, color: [f32; 3]) -> [f32; 3] {
+        if color == BG { self.background } else { color }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        THEMES[0]
+    }
+}
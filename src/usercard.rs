@@ -0,0 +1,177 @@
+use crate::chat::ChatMessage;
+use crate::history::History;
+use crate::input::InputLine;
+use crate::renderer::Screen;
+use crate::results::local_time;
+use crate::theme::Theme;
+use crate::view::rgb;
+
+// How many of the user's messages we pull out of the history
+const RECENT_LIMIT: usize = 100;
+
+const FIRST_TIME_FG: [f32; 4] = [0.4, 0.9, 0.4, 1.0];
+
+/// Panel describing a single chatter: who they are, what they've said and what we noted about
+/// them.
+pub struct UserCard {
+    pub user: String,
+    // The channel of the message the card was opened from, where moderation happens
+    pub channel: String,
+    display_name: String,
+    color: [f32; 4],
+    badges: Vec<String>,
+    first_seen: Option<i64>,
+    message_count: i64,
+    first_time: bool,
+    messages: Vec<ChatMessage>,
+    // How many lines up from the newest message the list is scrolled
+    scroll: usize,
+    note: String,
+    editing: Option<InputLine>,
+}
+
+impl UserCard {
+    /// Builds the card for the author of `m`, filling in what the history knows about them.
+    pub fn new(m: &ChatMessage, history: Option<&History>) -> Self {
+        let mut messages = Vec::new();
+        let mut first_seen = Some(m.timestamp);
+        let mut message_count = 1;
+        let mut note = String::new();
+        if let Some(h) = history {
+            messages = h.user_messages(&m.sender, RECENT_LIMIT).unwrap_or_default();
+            if let Ok((first, count)) = h.user_stats(&m.sender) {
+                first_seen = first.or(first_seen);
+                message_count = count.max(1);
+            }
+            note = h.note(&m.sender).ok().flatten().unwrap_or_default();
+        }
+        if messages.is_empty() {
+            messages.push(m.clone());
+        }
+
+        let first_time = m.tag("first-msg") == Some("1")
+            || messages.iter().any(|m| m.tag("first-msg") == Some("1"));
+
+        Self {
+            user: m.sender.clone(),
+            channel: m.channel.clone(),
+            display_name: m.display_name().to_string(),
+            color: rgb(m.color()),
+            badges: m.badges().iter().map(|(name, _)| name.to_string()).collect(),
+            first_seen,
+            message_count,
+            first_time,
+            messages,
+            scroll: 0,
+            note,
+            editing: None,
+        }
+    }
+
+    /// The newest message we have from this user.
+    pub fn latest(&self) -> Option<&ChatMessage> {
+        self.messages.last()
+    }
+
+    pub fn scroll_by(&mut self, delta: i32) {
+        let max = self.messages.len().saturating_sub(1) as i64;
+        self.scroll = (self.scroll as i64 - delta as i64).clamp(0, max) as usize;
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editing.is_some()
+    }
+
+    pub fn editing(&mut self) -> Option<&mut InputLine> {
+        self.editing.as_mut()
+    }
+
+    pub fn edit_note(&mut self) {
+        self.editing = Some(InputLine::with_text(&self.note));
+    }
+
+    /// Finishes editing, saving the note to the history database.
+    pub fn save_note(&mut self, history: Option<&History>) {
+        if let Some(input) = self.editing.take() {
+            self.note = input.text().trim().to_string();
+            if let Some(h) = history {
+                if let Err(e) = h.set_note(&self.user, &self.note) {
+                    println!("Failed to save note for {}: {}", self.user, e);
+                }
+            }
+        }
+    }
+
+    pub fn cancel_edit(&mut self) {
+        self.editing = None;
+    }
+
+    pub fn draw(&self, screen: &mut Screen, theme: &Theme) {
+        let cols = screen.cols();
+        let rows = screen.rows();
+        let width = cols.saturating_sub(4).min(90);
+        let left = (cols - width) / 2;
+        let top = 1;
+        let bottom = rows.saturating_sub(2);
+        if bottom <= top + 6 || width < 20 {
+            return;
+        }
+        let text_width = width.saturating_sub(2) as usize;
+
+        for row in top..=bottom {
+            screen.fill(row, left, width, theme.panel());
+        }
+        let print = |screen: &mut Screen, row: u32, s: &str, fg: [f32; 4], bg: [f32; 3]| {
+            let s: String = s.chars().take(text_width).collect();
+            screen.print_colored(row, left + 1, &s, fg, bg);
+        };
+
+        screen.fill(top, left, width, theme.header());
+        let name = if self.display_name.eq_ignore_ascii_case(&self.user) {
+            self.display_name.clone()
+        } else {
+            format!("{} ({})", self.display_name, self.user)
+        };
+        print(screen, top, &name, self.color, theme.header());
+
+        let badges = if self.badges.is_empty() { "none".to_string() } else { self.badges.join(", ") };
+        print(screen, top + 1, &format!("Badges: {}", badges), theme.meta(), theme.panel());
+        let first_seen = match self.first_seen {
+            Some(ts) => local_time(ts, "%Y-%m-%d %H:%M"),
+            None => "never".to_string(),
+        };
+        print(screen, top + 2, &format!("First seen: {}   Messages: {}", first_seen, self.message_count), theme.meta(), theme.panel());
+        if self.first_time {
+            print(screen, top + 3, "First time chatter", FIRST_TIME_FG, theme.panel());
+        }
+
+        let note = match &self.editing {
+            Some(input) => format!("Note: {}_", input.text()),
+            None if self.note.is_empty() => "Note: (n to add)".to_string(),
+            None => format!("Note: {}", self.note),
+        };
+        print(screen, top + 4, &note, theme.accent(), theme.panel());
+
+        screen.fill(bottom, left, width, theme.header());
+        print(screen, bottom, "t timeout  b ban  u unban  d delete latest  n note  Esc close", theme.meta(), theme.header());
+
+        // Recent messages fill the rest of the panel from the bottom up, newest last
+        let first_row = top + 6;
+        let mut lines: Vec<String> = Vec::new();
+        for m in self.messages.iter().rev().skip(self.scroll) {
+            let line = format!("{} {}", local_time(m.timestamp, "%m-%d %H:%M"), m.message);
+            let chars: Vec<char> = line.chars().collect();
+            let wrapped: Vec<String> = chars.chunks(text_width.max(1)).map(|c| c.iter().collect()).collect();
+            for l in wrapped.into_iter().rev() {
+                lines.push(l);
+            }
+            if lines.len() as u32 >= bottom - first_row {
+                break;
+            }
+        }
+        let available = (bottom - first_row) as usize;
+        for (i, line) in lines.iter().take(available).enumerate() {
+            print(screen, bottom - 1 - i as u32, line, theme.text(), theme.panel());
+        }
+    }
+}
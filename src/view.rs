@@ -0,0 +1,1041 @@
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use regex::{Regex, RegexBuilder};
+use crate::chat::{self, ChatMessage};
+use crate::hints::Link;
+use crate::renderer::{Screen, TextStyle};
+use crate::template::{Span, StyledLine};
+use crate::theme::Theme;
+
+pub const NORMAL: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+pub const DIM: [f32; 4] = [1.0, 1.0, 1.0, 0.35];
+pub const SUCCESS: [f32; 4] = [0.4, 0.9, 0.4, 1.0];
+pub const ERROR: [f32; 4] = [1.0, 0.45, 0.45, 1.0];
+pub const ERROR_BG: [f32; 3] = [0.25, 0.0, 0.0];
+pub const BG: [f32; 3] = [0.0, 0.0, 0.0];
+const UNDERLINE: [f32; 3] = [0.5, 0.7, 1.0];
+const PILL_BG: [f32; 3] = [0.25, 0.35, 0.6];
+const MATCH_BG: [f32; 3] = [0.45, 0.4, 0.1];
+const CURRENT_MATCH_BG: [f32; 3] = [0.75, 0.6, 0.1];
+
+/// Builds the regex a `/` search looks for, the pattern as typed when `regex`, otherwise literal
+/// text. Like vim's smartcase, case only matters once the pattern has a capital letter in it.
+pub fn search_regex(pattern: &str, regex: bool) -> Result<Regex, regex::Error> {
+    let pattern = if regex { pattern.to_string() } else { regex::escape(pattern) };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!pattern.chars().any(char::is_uppercase))
+        .build()
+}
+
+/// Converts a user's name colour to what the renderer wants.
+pub fn rgb(c: [u8; 3]) -> [f32; 4] {
+    [c[0] as f32 / 255.0, c[1] as f32 / 255.0, c[2] as f32 / 255.0, 1.0]
+}
+
+// Most pinned messages shown above the chat at once, newest first
+const MAX_PINS: usize = 3;
+
+// Longest channel name shown in front of each line in the merged view
+const CHANNEL_LABEL_LEN: usize = 8;
+
+// Scrollback kept by default, the SCROLLBACK environment variable changes it
+const DEFAULT_SCROLLBACK: usize = 10_000;
+// Whatever the count, old lines go once they take more than this
+const MAX_SCROLLBACK_BYTES: usize = 64 * 1024 * 1024;
+
+// New lines slide in easing out at this rate a second, and at least MIN_GLIDE pixels a second
+// so the end doesn't crawl
+const GLIDE_RATE: f32 = 12.0;
+const MIN_GLIDE: f32 = 120.0;
+
+// Messages are laid out from the first column, leaving a gutter like the original print_string
+// calls did.
+const LEFT_MARGIN: u32 = 1;
+
+struct Entry {
+    text: String,
+    // Colours and styles for parts of the text, from the line template
+    spans: Vec<Span>,
+    // Where wrapped lines start, in cells from the left of the text
+    indent: usize,
+    fg_color: [f32; 4],
+    bg_color: [f32; 3],
+    // The chat message this line shows, None for our own status lines
+    message: Option<ChatMessage>,
+    // Repeats of the message folded into this line, kept so selecting it takes in every author
+    repeats: Vec<ChatMessage>,
+}
+
+impl Entry {
+    // Roughly what the entry costs to keep, for the scrollback memory cap
+    fn size(&self) -> usize {
+        let messages: usize = self.messages().map(|m| {
+            std::mem::size_of::<ChatMessage>() + m.message.len() + m.sender.len() + m.channel.len() + m.id.len()
+                + m.tags.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>()
+        }).sum();
+        std::mem::size_of::<Entry>() + self.text.len() + self.spans.len() * std::mem::size_of::<Span>() + messages
+    }
+
+    // The message and everything folded into it
+    fn messages(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.message.iter().chain(&self.repeats)
+    }
+}
+
+// One screen line of a wrapped entry
+struct Line {
+    entry: usize,
+    // Where in the entry's displayed text, channel label included, the line starts
+    start: usize,
+    col: u32,
+    text: String,
+}
+
+// A message held at the top of the view until `until`
+struct Pin {
+    text: String,
+    bg_color: [f32; 3],
+    until: Instant,
+}
+
+/// The list of messages shown in the window. Messages are kept as text rather than cells so that
+/// an already laid out message can be rewritten and the whole view laid out again.
+pub struct ChatView {
+    // The newest entries, older ones are dropped past the scrollback limits. Ids count every entry
+    // ever pushed so they stay valid as the front goes, an entry's index is its id less `dropped`
+    entries: VecDeque<Entry>,
+    dropped: usize,
+    scrollback: usize,
+    bytes: usize,
+    // The entry at the bottom of the window and how many of its rows are below it, None while
+    // following the newest
+    scroll: Option<(usize, usize)>,
+    // Pixels the window is moved up from there, less than a row, for smooth scrolling
+    lift: f32,
+    // With smooth scrolling new lines slide in, this is how many pixels they still have to go
+    smooth: bool,
+    glide: f32,
+    // Entries before this have been seen by `animate`
+    seen: usize,
+    // Keyboard selection: the entry under the cursor and the entries marked for a bulk action
+    cursor: Option<usize>,
+    marked: BTreeSet<usize>,
+    // Where a visual selection started, everything from here to the cursor is marked
+    anchor: Option<usize>,
+    // What `/` is looking for, highlighted wherever it matches
+    search: Option<Regex>,
+    // How many messages it matches, kept up to date as entries come and go rather than
+    // rescanning the scrollback every frame
+    search_count: usize,
+    // What counts as a link, underlined and clickable
+    link_patterns: Vec<Regex>,
+    // With several channels joined every message is labelled with where it came from
+    show_channels: bool,
+    muted: HashSet<String>,
+    pins: Vec<Pin>,
+    theme: Theme,
+}
+
+impl ChatView {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            dropped: 0,
+            scrollback: DEFAULT_SCROLLBACK,
+            bytes: 0,
+            scroll: None,
+            lift: 0.0,
+            smooth: false,
+            glide: 0.0,
+            seen: 0,
+            cursor: None,
+            marked: BTreeSet::new(),
+            anchor: None,
+            search: None,
+            search_count: 0,
+            link_patterns: Vec::new(),
+            show_channels: false,
+            muted: HashSet::new(),
+            pins: Vec::new(),
+            theme: Theme::default(),
+        }
+    }
+
+    /// Appends a message and returns the id it can later be rewritten with.
+    pub fn push(&mut self, text: String, fg_color: [f32; 4]) -> usize {
+        self.push_colored(text, fg_color, BG)
+    }
+
+    pub fn push_colored(&mut self, text: String, fg_color: [f32; 4], bg_color: [f32; 3]) -> usize {
+        self.push_entry(Entry { text, spans: Vec::new(), indent: 0, fg_color, bg_color, message: None, repeats: Vec::new() })
+    }
+
+    /// Appends a line showing a chat message, keeping the message around for selection.
+    pub fn push_message(&mut self, m: ChatMessage, line: StyledLine, fg_color: [f32; 4]) -> usize {
+        self.push_message_colored(m, line, fg_color, BG)
+    }
+
+    pub fn push_message_colored(&mut self, m: ChatMessage, line: StyledLine, fg_color: [f32; 4], bg_color: [f32; 3]) -> usize {
+        self.push_entry(Entry {
+            text: line.text,
+            spans: line.spans,
+            indent: line.indent,
+            fg_color,
+            bg_color,
+            message: Some(m),
+            repeats: Vec::new(),
+        })
+    }
+
+    fn push_entry(&mut self, entry: Entry) -> usize {
+        self.bytes += entry.size();
+        if self.found(&entry) {
+            self.search_count += 1;
+        }
+        self.entries.push_back(entry);
+        while self.entries.len() > self.scrollback || (self.bytes > MAX_SCROLLBACK_BYTES && self.entries.len() > 1) {
+            let old = self.entries.pop_front().unwrap();
+            self.bytes -= old.size();
+            if self.found(&old) {
+                self.search_count -= 1;
+            }
+            self.dropped += 1;
+        }
+        // Whatever pointed at dropped entries goes with them
+        let first = self.dropped;
+        self.marked = self.marked.split_off(&first);
+        self.cursor = self.cursor.filter(|c| *c >= first);
+        self.anchor = self.anchor.filter(|a| *a >= first);
+        if self.scroll.is_some_and(|(id, _)| id < first) {
+            self.scroll = Some((first, 0));
+        }
+        self.dropped + self.entries.len() - 1
+    }
+
+    /// Keeps at most `lines` entries, for the SCROLLBACK setting.
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.scrollback = lines.max(1);
+    }
+
+    fn entry(&self, id: usize) -> Option<&Entry> {
+        self.entries.get(id.checked_sub(self.dropped)?)
+    }
+
+    fn entry_mut(&mut self, id: usize) -> Option<&mut Entry> {
+        self.entries.get_mut(id.checked_sub(self.dropped)?)
+    }
+
+    // Every id still held, oldest first
+    fn ids(&self) -> Range<usize> {
+        self.dropped..self.dropped + self.entries.len()
+    }
+
+    /// Swaps a line that has already been pushed for another message, e.g. once a message we sent
+    /// is confirmed.
+    pub fn replace(&mut self, id: usize, m: ChatMessage, line: StyledLine, fg_color: [f32; 4]) {
+        self.replace_colored(id, m, line, fg_color, BG);
+    }
+
+    pub fn replace_colored(&mut self, id: usize, m: ChatMessage, line: StyledLine, fg_color: [f32; 4], bg_color: [f32; 3]) {
+        let entry = Entry {
+            text: line.text,
+            spans: line.spans,
+            indent: line.indent,
+            fg_color,
+            bg_color,
+            message: Some(m),
+            repeats: Vec::new(),
+        };
+        let size = entry.size();
+        let found = self.found(&entry);
+        let was_found = self.entry(id).is_some_and(|e| self.found(e));
+        if let Some(old) = self.entry_mut(id) {
+            let old_size = old.size();
+            *old = entry;
+            self.bytes = self.bytes + size - old_size;
+            self.search_count = self.search_count + found as usize - was_found as usize;
+        }
+    }
+
+    pub fn set_link_patterns(&mut self, patterns: Vec<Regex>) {
+        self.link_patterns = patterns;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    pub fn set_show_channels(&mut self, show: bool) {
+        self.show_channels = show;
+    }
+
+    pub fn shows_channels(&self) -> bool {
+        self.show_channels
+    }
+
+    /// Hides or shows every message from a channel, returning whether it is now muted.
+    pub fn toggle_mute(&mut self, channel: &str) -> bool {
+        let muted = self.muted.insert(channel.to_string());
+        if !muted {
+            self.muted.remove(channel);
+        }
+        self.count_search();
+        muted
+    }
+
+    fn visible(&self, entry: &Entry) -> bool {
+        entry.message.as_ref().is_none_or(|m| !self.muted.contains(&m.channel))
+    }
+
+    /// The coloured channel label drawn in front of a message, with its trailing space.
+    fn channel_label(&self, entry: &Entry) -> Option<(String, [f32; 4])> {
+        let m = entry.message.as_ref().filter(|_| self.show_channels)?;
+        let name: String = m.channel.chars().take(CHANNEL_LABEL_LEN).collect();
+        Some((format!("{} ", name), rgb(chat::default_color(&m.channel))))
+    }
+
+    /// What an entry looks like laid out, with its channel label if it has one.
+    fn display_text(&self, entry: &Entry) -> String {
+        match self.channel_label(entry) {
+            Some((label, _)) => label + &entry.text,
+            None => entry.text.clone(),
+        }
+    }
+
+    /// Keeps a line at the top of the view for `duration`.
+    pub fn pin(&mut self, text: String, bg_color: [f32; 3], duration: Duration) {
+        self.pins.push(Pin { text, bg_color, until: Instant::now() + duration });
+    }
+
+    /// Drops pins whose time is up, returning whether any went.
+    pub fn expire_pins(&mut self, now: Instant) -> bool {
+        let before = self.pins.len();
+        self.pins.retain(|p| p.until > now);
+        self.pins.len() != before
+    }
+
+    // Rows at the top taken by pins, the chat starts below them
+    fn pin_rows(&self) -> usize {
+        self.pins.len().min(MAX_PINS)
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.cursor.is_some() || !self.marked.is_empty()
+    }
+
+    // Entries holding chat messages that aren't hidden, oldest first
+    fn messages(&self) -> Vec<usize> {
+        self.ids()
+            .filter(|i| self.entry(*i).is_some_and(|e| e.message.is_some() && self.visible(e)))
+            .collect()
+    }
+
+    /// Moves the selection cursor by `delta` chat messages, starting from the newest one.
+    pub fn move_cursor(&mut self, delta: i32) {
+        let messages = self.messages();
+        let last = match messages.len().checked_sub(1) {
+            Some(l) => l,
+            None => return,
+        };
+        // Without a cursor it starts just past the newest, so k picks the newest and gg the oldest
+        let from = self.cursor.and_then(|c| messages.iter().position(|i| *i == c)).unwrap_or(last + 1);
+        let pos = (from as i64 + delta as i64).clamp(0, last as i64) as usize;
+        self.cursor = Some(messages[pos]);
+        self.mark_visual(&messages);
+    }
+
+    /// Moves the cursor to the nearest message after it, or before it unless `forward`, that
+    /// `pred` accepts. Returns false when there is none.
+    pub fn jump_to(&mut self, forward: bool, pred: impl Fn(&ChatMessage) -> bool) -> bool {
+        self.jump_to_entry(forward, |entry| entry.message.as_ref().is_some_and(&pred))
+    }
+
+    fn jump_to_entry(&mut self, forward: bool, pred: impl Fn(&Entry) -> bool) -> bool {
+        let messages = self.messages();
+        let current = self.cursor.and_then(|c| messages.iter().position(|i| *i == c));
+        let found = if forward {
+            let from = current.map_or(messages.len(), |c| c + 1);
+            messages.iter().skip(from).find(|i| self.entry(**i).is_some_and(&pred))
+        } else {
+            let to = current.unwrap_or(messages.len());
+            messages[..to].iter().rev().find(|i| self.entry(**i).is_some_and(&pred))
+        };
+        match found {
+            Some(i) => {
+                self.cursor = Some(*i);
+                self.mark_visual(&messages);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Where the cursor is, to put it back with `set_cursor_position`.
+    pub fn cursor_position(&self) -> Option<usize> {
+        self.cursor
+    }
+
+    pub fn set_cursor_position(&mut self, cursor: Option<usize>) {
+        self.cursor = cursor.filter(|c| self.ids().contains(c));
+    }
+
+    /// Highlights every match of `search` in the text of messages, None stops searching.
+    pub fn set_search(&mut self, search: Option<Regex>) {
+        self.search = search;
+        self.count_search();
+    }
+
+    pub fn has_search(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// How many messages the search matches.
+    pub fn search_count(&self) -> usize {
+        self.search_count
+    }
+
+    // Whether an entry counts towards `search_count`, a shown message the search matches
+    fn found(&self, entry: &Entry) -> bool {
+        entry.message.is_some() && self.visible(entry) && self.search.as_ref().is_some_and(|re| re.is_match(&entry.text))
+    }
+
+    fn count_search(&mut self) {
+        self.search_count = self.entries.iter().filter(|e| self.found(e)).count();
+    }
+
+    /// Moves the cursor to the next message matching the search, newer when `forward`. Returns
+    /// false when there is no search or no further match.
+    pub fn next_match(&mut self, forward: bool) -> bool {
+        match self.search.clone() {
+            Some(re) => self.jump_to_entry(forward, |entry| re.is_match(&entry.text)),
+            None => false,
+        }
+    }
+
+    // Where the search matches an entry's text, in chars of its displayed text, which starts
+    // `offset` chars of channel label earlier
+    fn search_matches(&self, entry: &Entry, offset: usize) -> Vec<Range<usize>> {
+        let re = match &self.search {
+            Some(re) if entry.message.is_some() => re,
+            _ => return Vec::new(),
+        };
+        re.find_iter(&entry.text)
+            .filter(|m| !m.as_str().is_empty())
+            .map(|m| {
+                let start = offset + entry.text[..m.start()].chars().count();
+                start..start + m.as_str().chars().count()
+            })
+            .collect()
+    }
+
+    /// Starts selecting a range of messages from the one under the cursor, or the newest.
+    pub fn start_visual(&mut self) {
+        if self.cursor.is_none() {
+            self.move_cursor(0);
+        }
+        self.anchor = self.cursor;
+        self.mark_visual(&self.messages());
+    }
+
+    /// Leaves visual selection, unmarking the range.
+    pub fn end_visual(&mut self) {
+        if self.anchor.take().is_some() {
+            self.marked.clear();
+        }
+    }
+
+    pub fn in_visual(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    fn mark_visual(&mut self, messages: &[usize]) {
+        let (anchor, cursor) = match (self.anchor, self.cursor) {
+            (Some(a), Some(c)) => (a.min(c), a.max(c)),
+            _ => return,
+        };
+        self.marked = messages.iter().copied().filter(|i| (anchor..=cursor).contains(i)).collect();
+    }
+
+    pub fn toggle_mark(&mut self) {
+        if let Some(c) = self.cursor {
+            if !self.marked.remove(&c) {
+                self.marked.insert(c);
+            }
+        }
+    }
+
+    /// Marks every message among the newest `limit` entries whose text matches, returning how
+    /// many were marked.
+    pub fn mark_matching(&mut self, re: &Regex, limit: usize) -> usize {
+        let start = self.entries.len().saturating_sub(limit);
+        let mut count = 0;
+        for (i, entry) in self.entries.iter().enumerate().skip(start) {
+            if self.visible(entry) && entry.messages().any(|m| re.is_match(&m.message)) {
+                self.marked.insert(self.dropped + i);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.cursor = None;
+        self.anchor = None;
+        self.marked.clear();
+    }
+
+    /// The marked messages, or the one under the cursor when nothing is marked, along with the
+    /// repeats folded into them.
+    pub fn selected_messages(&self) -> Vec<&ChatMessage> {
+        let selected: Vec<usize> = if self.marked.is_empty() {
+            self.cursor.into_iter().collect()
+        } else {
+            self.marked.iter().copied().collect()
+        };
+        selected.iter()
+            .filter_map(|i| self.entry(*i))
+            .flat_map(Entry::messages)
+            .collect()
+    }
+
+    /// Folds a repeat of a message that has already been pushed into its line, replacing the text
+    /// to show the count. Styles stay on the chars they covered, so the text is meant to only add
+    /// to the end of the line.
+    pub fn fold(&mut self, id: usize, text: String, repeat: ChatMessage) {
+        let was_found = self.entry(id).is_some_and(|e| self.found(e));
+        if let Some(entry) = self.entry_mut(id) {
+            let old_size = entry.size();
+            entry.text = text;
+            entry.repeats.push(repeat);
+            let size = entry.size();
+            self.bytes = self.bytes + size - old_size;
+        }
+        let found = self.entry(id).is_some_and(|e| self.found(e));
+        self.search_count = self.search_count + found as usize - was_found as usize;
+    }
+
+    /// Wraps an entry to `width`, nothing when it is hidden. Continuation lines are indented to
+    /// line up with the start of the message text, unless that would leave too little room.
+    fn wrap(&self, id: usize, width: usize) -> Vec<Line> {
+        let entry = match self.entry(id).filter(|e| self.visible(e)) {
+            Some(e) => e,
+            None => return Vec::new(),
+        };
+        let chars: Vec<char> = self.display_text(entry).chars().collect();
+        let label_len = self.channel_label(entry).map_or(0, |(label, _)| label.chars().count());
+        let indent = Some(label_len + entry.indent).filter(|i| *i < width / 2).unwrap_or(0);
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let col = if start == 0 { 0 } else { indent };
+            let end = (start + width - col).min(chars.len());
+            lines.push(Line {
+                entry: id,
+                start,
+                col: LEFT_MARGIN + col as u32,
+                text: chars[start..end].iter().collect(),
+            });
+            start = end;
+        }
+        lines
+    }
+
+    fn prev_visible(&self, id: usize) -> Option<usize> {
+        (self.dropped..id).rev().find(|i| self.entry(*i).is_some_and(|e| self.visible(e)))
+    }
+
+    fn next_visible(&self, id: usize) -> Option<usize> {
+        (id + 1..self.ids().end).find(|i| self.entry(*i).is_some_and(|e| self.visible(e)))
+    }
+
+    // Rows the chat gets, below the pins
+    fn chat_rows(&self, screen: &Screen) -> usize {
+        (screen.rows() as usize).saturating_sub(self.pin_rows())
+    }
+
+    /// The wrapped lines in a window of `rows`, top to bottom, when scrolled to `at`. Only the
+    /// entries in view get laid out. Also returns where `at` really ends up: scrolling past the
+    /// oldest line stops with it at the top, and reaching the newest follows it again.
+    fn window(&self, width: usize, rows: usize, at: Option<(usize, usize)>) -> (VecDeque<Line>, Option<(usize, usize)>) {
+        let mut lines = VecDeque::new();
+        let last = self.prev_visible(self.ids().end);
+        let (bottom, hidden) = match at.or(last.map(|l| (l, 0))) {
+            Some(at) => at,
+            None => return (lines, None),
+        };
+
+        // Upwards from the bottom row
+        let mut current = self.wrap(bottom, width);
+        let mut below: VecDeque<Line> = current.split_off(current.len().saturating_sub(hidden)).into();
+        let mut id = bottom;
+        while lines.len() < rows {
+            match current.pop() {
+                Some(line) => lines.push_front(line),
+                None => match self.prev_visible(id) {
+                    Some(prev) => {
+                        id = prev;
+                        current = self.wrap(prev, width);
+                    },
+                    None => break,
+                },
+            }
+        }
+
+        // Scrolled past the oldest line, the rows left over show what is below instead
+        let mut at = (bottom, below.len());
+        let mut id = bottom;
+        while lines.len() < rows {
+            match below.pop_front() {
+                Some(line) => {
+                    at = (line.entry, below.len());
+                    lines.push_back(line);
+                },
+                None => match self.next_visible(id) {
+                    Some(next) => {
+                        id = next;
+                        below = self.wrap(next, width).into();
+                    },
+                    None => break,
+                },
+            }
+        }
+        (lines, Some(at).filter(|at| Some(*at) != last.map(|l| (l, 0))))
+    }
+
+    /// Scrolls by `delta` rows, back into the scrollback when negative. New messages don't move
+    /// the view until it is scrolled back down to the newest.
+    pub fn scroll_by(&mut self, delta: i32, screen: &Screen) {
+        self.lift = 0.0;
+        self.glide = 0.0;
+        self.move_rows(delta, screen);
+    }
+
+    fn move_rows(&mut self, delta: i32, screen: &Screen) {
+        let width = Self::width(screen);
+        let (mut id, mut hidden) = match self.scroll.or_else(|| Some((self.prev_visible(self.ids().end)?, 0))) {
+            Some(at) => at,
+            None => return,
+        };
+        let mut left = delta.unsigned_abs() as usize;
+        if delta < 0 {
+            while left > 0 {
+                let total = self.wrap(id, width).len();
+                let above = total.saturating_sub(hidden + 1);
+                if above >= left {
+                    hidden += left;
+                    break;
+                }
+                match self.prev_visible(id) {
+                    Some(prev) => {
+                        left -= above + 1;
+                        id = prev;
+                        hidden = 0;
+                    },
+                    None => {
+                        hidden = total.saturating_sub(1);
+                        break;
+                    },
+                }
+            }
+        } else {
+            while left > 0 {
+                if hidden >= left {
+                    hidden -= left;
+                    break;
+                }
+                match self.next_visible(id) {
+                    Some(next) => {
+                        left -= hidden + 1;
+                        id = next;
+                        hidden = self.wrap(next, width).len().saturating_sub(1);
+                    },
+                    None => {
+                        hidden = 0;
+                        break;
+                    },
+                }
+            }
+        }
+        self.scroll = self.window(width, self.chat_rows(screen), Some((id, hidden))).1;
+    }
+
+    /// Scrolls a window's height, less a row to keep some context.
+    pub fn scroll_pages(&mut self, pages: i32, screen: &Screen) {
+        let page = (self.chat_rows(screen) as i32 - 1).max(1);
+        self.scroll_by(pages * page, screen);
+    }
+
+    /// Scrolls by `pixels`, which needn't be whole rows, down towards the newest when positive.
+    pub fn scroll_pixels(&mut self, pixels: f32, screen: &Screen) {
+        self.glide = 0.0;
+        self.shift(pixels, screen);
+    }
+
+    fn shift(&mut self, pixels: f32, screen: &Screen) {
+        let (_, cell_height) = screen.cell_size();
+        let lift = self.lift + pixels;
+        let rows = (lift / cell_height).floor();
+        let before = self.scroll;
+        if rows != 0.0 {
+            self.move_rows(rows as i32, screen);
+        }
+        self.lift = lift - rows * cell_height;
+        // Nothing newer to lift into view, or nothing older to go down to
+        if self.scroll.is_none() || (rows < 0.0 && self.scroll == before) {
+            self.lift = 0.0;
+        }
+    }
+
+    /// Goes back to following the newest messages.
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll = None;
+        self.lift = 0.0;
+        self.glide = 0.0;
+    }
+
+    /// Lines the window back up with the rows, for overlays that point at what is on screen.
+    pub fn snap(&mut self, screen: &Screen) {
+        if self.glide > 0.0 {
+            self.scroll_to_bottom();
+        } else if self.lift >= screen.cell_size().1 / 2.0 {
+            self.scroll_by(1, screen);
+        } else {
+            self.lift = 0.0;
+        }
+    }
+
+    /// Slides lines in rather than jumping when smooth scrolling is on.
+    pub fn set_smooth(&mut self, on: bool) {
+        self.smooth = on;
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.smooth
+    }
+
+    /// Moves lines that arrived since the last frame into view a little at a time, `dt` seconds
+    /// on from that frame. True while they have further to go.
+    pub fn animate(&mut self, dt: f32, screen: &Screen) -> bool {
+        let end = self.ids().end;
+        let new = self.seen.max(self.dropped)..end;
+        self.seen = end;
+        if self.smooth && (self.scroll.is_none() || self.glide > 0.0) {
+            let width = Self::width(screen);
+            let rows: usize = new.map(|id| self.wrap(id, width).len()).sum();
+            // Put the window back where it was before they came and slide down from there
+            let pixels = rows.min(self.chat_rows(screen)) as f32 * screen.cell_size().1;
+            if pixels > 0.0 {
+                self.shift(-pixels, screen);
+                self.glide += pixels;
+            }
+        }
+        if self.glide > 0.0 {
+            let step = (self.glide * (1.0 - (-GLIDE_RATE * dt).exp())).max(MIN_GLIDE * dt);
+            if step >= self.glide || self.scroll.is_none() {
+                // Gliding always ends at the newest, whatever rounding is left over
+                self.scroll_to_bottom();
+            } else {
+                self.glide -= step;
+                self.shift(step, screen);
+            }
+        }
+        self.glide > 0.0
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.glide > 0.0
+    }
+
+    /// Scrolls just enough to bring the message under the cursor into view.
+    pub fn reveal_cursor(&mut self, screen: &Screen) {
+        let cursor = match self.cursor {
+            Some(c) => c,
+            None => return,
+        };
+        let width = Self::width(screen);
+        let rows = self.chat_rows(screen);
+        let (lines, _) = self.window(width, rows, self.scroll);
+        let shown = lines.iter().filter(|l| l.entry == cursor).count();
+        if shown > 0 && shown >= self.wrap(cursor, width).len().min(rows) {
+            return;
+        }
+        if lines.back().is_none_or(|l| cursor >= l.entry) {
+            self.lift = 0.0;
+            self.glide = 0.0;
+            // Below the window, or cut off at its bottom: bring it up to the bottom row
+            self.scroll = self.window(width, rows, Some((cursor, 0))).1;
+        } else {
+            self.lift = 0.0;
+            self.glide = 0.0;
+            // Above: bring it down to the top row
+            let mut id = cursor;
+            let mut used = self.wrap(cursor, width).len();
+            while used < rows {
+                match self.next_visible(id) {
+                    Some(next) => {
+                        id = next;
+                        used += self.wrap(next, width).len();
+                    },
+                    None => break,
+                }
+            }
+            self.scroll = self.window(width, rows, Some((id, used.saturating_sub(rows)))).1;
+        }
+    }
+
+    // The "N new messages" pill along the bottom while scrolled up, as (row, col, text)
+    fn pill(&self, screen: &Screen) -> Option<(u32, u32, String)> {
+        let (bottom, _) = self.scroll.filter(|_| self.glide == 0.0)?;
+        let unseen = (bottom + 1..self.ids().end)
+            .filter(|i| self.entry(*i).is_some_and(|e| e.message.is_some() && self.visible(e)))
+            .count();
+        if unseen == 0 {
+            return None;
+        }
+        let text = format!(" {} new message{} ↓ ", unseen, if unseen == 1 { "" } else { "s" });
+        let len = text.chars().count() as u32;
+        Some((screen.rows().saturating_sub(1), screen.cols().saturating_sub(len) / 2, text))
+    }
+
+    /// Whether a cell is on the new messages pill, which scrolls back down when clicked.
+    pub fn pill_at(&self, screen: &Screen, col: u32, row: u32) -> bool {
+        self.pill(screen).is_some_and(|(r, c, text)| row == r && (c..c + text.chars().count() as u32).contains(&col))
+    }
+
+    // The row after `at`, towards the newest
+    fn row_after(&self, (id, hidden): (usize, usize), width: usize) -> Option<(usize, usize)> {
+        if hidden > 0 {
+            return Some((id, hidden - 1));
+        }
+        let next = self.next_visible(id)?;
+        Some((next, self.wrap(next, width).len().saturating_sub(1)))
+    }
+
+    /// The cell under a point in window coordinates, allowing for the chat being scrolled part of
+    /// a row.
+    pub fn cell_at(&self, screen: &Screen, x: f64, y: f64) -> (u32, u32) {
+        let (col, row) = screen.cell_at(x, y);
+        if (row as usize) < self.pin_rows() {
+            return (col, row);
+        }
+        screen.cell_at(x, y + self.lift as f64)
+    }
+
+    // The lines on screen, each with the row it is drawn on before the lift moves them
+    fn visible_lines(&self, screen: &Screen) -> Vec<(u32, Line)> {
+        let top = self.pin_rows();
+        let width = Self::width(screen);
+        let rows = self.chat_rows(screen);
+        let (lines, _) = match self.scroll.filter(|_| self.lift > 0.0).and_then(|at| self.row_after(at, width)) {
+            // Part way to the next row, which peeks in along the bottom
+            Some(after) => self.window(width, rows + 1, Some(after)),
+            None => self.window(width, rows, self.scroll),
+        };
+        lines.into_iter().enumerate().map(|(n, line)| ((n + top) as u32, line)).collect()
+    }
+
+    /// The colour and style of a char of an entry's displayed text.
+    fn style_at(&self, entry: &Entry, label: &Option<(String, [f32; 4])>, pos: usize) -> ([f32; 4], TextStyle) {
+        let label_len = label.as_ref().map_or(0, |(l, _)| l.chars().count());
+        if pos < label_len {
+            return (label.as_ref().unwrap().1, TextStyle::default());
+        }
+        match entry.spans.iter().find(|s| s.range.contains(&(pos - label_len))) {
+            // Whatever the line is drawn with still fades the span, so dimmed lines stay dim
+            Some(span) => match span.fg_color {
+                Some(c) => ([c[0], c[1], c[2], c[3] * entry.fg_color[3]], span.style),
+                None => (entry.fg_color, span.style),
+            },
+            None => (entry.fg_color, TextStyle::default()),
+        }
+    }
+
+    fn width(screen: &Screen) -> usize {
+        screen.cols().saturating_sub(LEFT_MARGIN).max(1) as usize
+    }
+
+    /// The chat message drawn on a screen row, if any.
+    pub fn message_at_row(&self, screen: &Screen, row: u32) -> Option<&ChatMessage> {
+        let (_, line) = self.visible_lines(screen).into_iter().find(|(r, _)| *r == row)?;
+        self.entry(line.entry)?.message.as_ref()
+    }
+
+    /// Every link matched in the rows currently on screen.
+    pub fn links(&self, screen: &Screen) -> Vec<Link> {
+        let visible = self.visible_lines(screen);
+        let mut links = Vec::new();
+        // Each entry's visible lines in turn
+        for group in visible.chunk_by(|a, b| a.1.entry == b.1.entry) {
+            let text = match self.entry(group[0].1.entry) {
+                Some(entry) => self.display_text(entry),
+                None => continue,
+            };
+            for (rule, chars, found) in self.link_matches(&text) {
+                let segments: Vec<_> = group.iter().filter_map(|(row, line)| {
+                    let from = chars.start.max(line.start);
+                    let to = chars.end.min(line.start + line.text.chars().count());
+                    (from < to).then(|| (*row, line.col + (from - line.start) as u32, to - from))
+                }).collect();
+                if !segments.is_empty() {
+                    links.push(Link { text: found, rule, segments });
+                }
+            }
+        }
+        links
+    }
+
+    // Every link in a text with the rule it matched and where it is, in chars since that's what
+    // the layout wraps on. Where patterns overlap the earlier rule keeps the text, so one link
+    // isn't underlined and labelled twice.
+    fn link_matches(&self, text: &str) -> Vec<(usize, Range<usize>, String)> {
+        let mut matches: Vec<(usize, Range<usize>, String)> = Vec::new();
+        for (rule, re) in self.link_patterns.iter().enumerate() {
+            for m in re.find_iter(text) {
+                let start = text[..m.start()].chars().count();
+                let chars = start..start + m.as_str().chars().count();
+                if !matches.iter().any(|(_, taken, _)| taken.start < chars.end && chars.start < taken.end) {
+                    matches.push((rule, chars, m.as_str().to_string()));
+                }
+            }
+        }
+        matches.sort_by_key(|(_, chars, _)| chars.start);
+        matches
+    }
+
+    /// The link drawn at a cell, if any.
+    pub fn link_at(&self, screen: &Screen, col: u32, row: u32) -> Option<Link> {
+        self.links(screen).into_iter().find(|l| l.contains(col, row))
+    }
+
+    /// The chat message under the selection cursor.
+    pub fn cursor_message(&self) -> Option<&ChatMessage> {
+        self.entry(self.cursor?)?.message.as_ref()
+    }
+
+    /// Lays the messages in view out onto the screen, wrapping at the window width.
+    pub fn draw(&self, screen: &mut Screen) {
+        let width = Self::width(screen);
+        for (row, pin) in self.pins.iter().rev().take(MAX_PINS).enumerate() {
+            let text: String = pin.text.chars().take(width).collect();
+            screen.fill_row(row as u32, pin.bg_color);
+            screen.print_colored(row as u32, LEFT_MARGIN, &text, self.theme.fg(NORMAL), pin.bg_color);
+        }
+
+        screen.begin_scroll(self.lift, self.pin_rows() as u32);
+        for (row, line) in self.visible_lines(screen) {
+            let entry = match self.entry(line.entry) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let bg_color = if self.cursor == Some(line.entry) {
+                self.theme.cursor()
+            } else if self.marked.contains(&line.entry) {
+                self.theme.marked()
+            } else {
+                self.theme.bg(entry.bg_color)
+            };
+            // Continuation lines get their indent painted too so the background reads as one block
+            if line.col > LEFT_MARGIN {
+                screen.fill(row, LEFT_MARGIN, line.col - LEFT_MARGIN, bg_color);
+            }
+
+            // Print runs of chars that share a colour, style and background
+            let label = self.channel_label(entry);
+            let found = self.search_matches(entry, label.as_ref().map_or(0, |(l, _)| l.chars().count()));
+            let match_bg = if self.cursor == Some(line.entry) { CURRENT_MATCH_BG } else { MATCH_BG };
+            let mut col = line.col;
+            let mut run = String::new();
+            let mut run_style = None;
+            for (n, c) in line.text.chars().enumerate() {
+                let pos = line.start + n;
+                let (fg, text_style) = self.style_at(entry, &label, pos);
+                let bg = if found.iter().any(|r| r.contains(&pos)) { match_bg } else { bg_color };
+                let style = (fg, text_style, bg);
+                if run_style.is_some_and(|s| s != style) {
+                    let (fg, text_style, bg) = run_style.unwrap();
+                    screen.print_styled(row, col, &run, self.theme.fg(fg), bg, text_style);
+                    col += run.chars().count() as u32;
+                    run.clear();
+                }
+                run_style = Some(style);
+                run.push(c);
+            }
+            if let Some((fg, text_style, bg)) = run_style {
+                screen.print_styled(row, col, &run, self.theme.fg(fg), bg, text_style);
+            }
+        }
+        let (cell_width, cell_height) = screen.cell_size();
+        for link in self.links(screen) {
+            for (row, col, len) in link.segments {
+                let y = (row + 1) as f32 * cell_height - 2.0;
+                screen.rect(col as f32 * cell_width, y, len as f32 * cell_width, 1.0, UNDERLINE);
+            }
+        }
+        screen.end_scroll();
+
+        if let Some((row, col, text)) = self.pill(screen) {
+            screen.print_colored(row, col, &text, NORMAL, PILL_BG);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const FG: [f32; 4] = [1.0; 4];
+
+    fn push(view: &mut ChatView, channel: &str, text: &str) -> usize {
+        let line = StyledLine { text: text.to_string(), spans: Vec::new(), indent: 0 };
+        view.push_message(ChatMessage::sent(channel, "someone", text, HashMap::new()), line, FG)
+    }
+
+    #[test]
+    fn search_count_follows_the_entries() {
+        let mut view = ChatView::new();
+        view.set_scrollback(3);
+        let first = push(&mut view, "a", "hello there");
+        push(&mut view, "b", "goodbye");
+        view.push("hello from the client".to_string(), FG);
+        view.set_search(Some(search_regex("hello", false).unwrap()));
+        // Only chat messages count
+        assert_eq!(view.search_count(), 1);
+
+        // The oldest match scrolls out as a new one comes in
+        push(&mut view, "b", "hello again");
+        assert_eq!(view.search_count(), 1);
+        assert!(view.entry(first).is_none());
+
+        let bye = push(&mut view, "b", "bye");
+        view.fold(bye, "bye hello ×2".to_string(), ChatMessage::sent("b", "someone", "bye", HashMap::new()));
+        assert_eq!(view.search_count(), 2);
+        let line = StyledLine { text: "bye".to_string(), spans: Vec::new(), indent: 0 };
+        view.replace(bye, ChatMessage::sent("b", "someone", "bye", HashMap::new()), line, FG);
+        assert_eq!(view.search_count(), 1);
+
+        assert!(view.toggle_mute("b"));
+        assert_eq!(view.search_count(), 0);
+        assert!(!view.toggle_mute("b"));
+        assert_eq!(view.search_count(), 1);
+        view.set_search(None);
+        assert_eq!(view.search_count(), 0);
+    }
+
+    #[test]
+    fn overlapping_links_match_once() {
+        let mut view = ChatView::new();
+        view.set_link_patterns(vec![
+            Regex::new(r"https?://\S+").unwrap(),
+            Regex::new(r"https://clips\.twitch\.tv/\S+").unwrap(),
+            Regex::new(r"\bclip\b").unwrap(),
+        ]);
+        let found = view.link_matches("clip → https://clips.twitch.tv/Abc and https://example.com");
+        assert_eq!(found, vec![
+            (2, 0..4, "clip".to_string()),
+            (0, 7..34, "https://clips.twitch.tv/Abc".to_string()),
+            (0, 39..58, "https://example.com".to_string()),
+        ]);
+    }
+}